@@ -1,7 +1,9 @@
 use std::fmt;
 use std::io::{Stdout, Write};
-use std::ops::RangeBounds;
+use std::ops::{Bound, RangeBounds};
 use std::slice::SliceIndex;
+
+use crate::editor::marks::Edit;
 struct SliceDisplay<'a, T: 'a>(&'a [T]);
 
 impl<'a, T: fmt::Display + 'a> fmt::Display for SliceDisplay<'a, T> {
@@ -24,6 +26,8 @@ pub struct AppendBuffer {
     pub(crate) buffer: Vec<u8>,
     pub(crate) size: u32,
     pub(crate) new_lines: Vec<i32>,
+    // Edits since the marks were last moved, see Marks::apply
+    pub(crate) edits: Vec<Edit>,
 }
 impl AppendBuffer {
     pub(crate) fn append(&mut self, input_stream: &[u8]) {
@@ -36,11 +40,24 @@ impl AppendBuffer {
         self.size += s.len() as u32;
     }
     pub(crate) fn insert(&mut self, index: usize, input_stream: u8) {
+        self.record(index, index, 1);
         self.buffer.insert(index, input_stream);
         self.size += 1;
         self.update_buffers();
     }
+    pub(crate) fn insert_slice(&mut self, index: usize, input_stream: &[u8]) {
+        self.record(index, index, input_stream.len());
+        self.buffer
+            .splice(index..index, input_stream.iter().cloned());
+        self.update_buffers();
+    }
+    pub(crate) fn replace_slice(&mut self, range: std::ops::Range<usize>, input_stream: &[u8]) {
+        self.record(range.start, range.end, input_stream.len());
+        self.buffer.splice(range, input_stream.iter().cloned());
+        self.update_buffers();
+    }
     pub(crate) fn remove(&mut self, index: usize) {
+        self.record(index, index + 1, 0);
         self.buffer.remove(index);
         self.size -= 1;
         // update later
@@ -50,6 +67,17 @@ impl AppendBuffer {
     where
         R: RangeBounds<usize>,
     {
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(e) => e + 1,
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => self.buffer.len(),
+        };
+        self.record(start, end, 0);
         self.buffer.drain(range);
         self.size -= 1;
         // update later
@@ -86,7 +114,23 @@ impl AppendBuffer {
         }
         self.free();
     }
+    fn record(&mut self, start: usize, end: usize, inserted: usize) {
+        self.edits.push(Edit {
+            at: start,
+            removed: self.buffer[start..end].to_vec(),
+            inserted,
+            line_start: start == 0 || self.buffer[start - 1] == b'\n',
+        });
+    }
     pub(crate) fn update_buffers(&mut self) {
+        // The text always ends in a newline, so its last line is a line like the others
+        // and an empty buffer still has one. Files without it have 'noeol'.
+        // The newline goes in the journal like any edit, marks past it move with it.
+        if self.buffer.last() != Some(&b'\n') {
+            let end = self.buffer.len();
+            self.record(end, end, 1);
+            self.buffer.push(b'\n');
+        }
         self.size = self.buffer.len() as u32;
         // TODO: Don't have to iterate evrytime
         self.new_lines = self
@@ -115,3 +159,25 @@ where
         &self.buffer[index]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newline_put_back_at_the_end_is_journaled() {
+        let mut data = AppendBuffer::default();
+        data.buffer = b"one\ntwo\n".to_vec();
+        data.update_buffers();
+        assert!(data.edits.is_empty());
+        data.remove(7);
+        data.update_buffers();
+        assert_eq!(data.buffer, b"one\ntwo\n");
+        let put_back = data.edits.last().unwrap();
+        assert_eq!((put_back.at, put_back.inserted), (7, 1));
+        assert!(put_back.removed.is_empty());
+        // The start of the last line stays where it was
+        let offset = data.edits.iter().try_fold(4, |pos, edit| edit.shift(pos));
+        assert_eq!(offset, Some(4));
+    }
+}
@@ -0,0 +1,206 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+pub use crate::editor::Terminal;
+
+// The "+ and "* registers. Yanks always go out as an OSC 52 escape so the terminal
+// (even one on the other side of ssh) can set its clipboard, and through a copy
+// command like xclip/wl-copy/pbcopy when one is around. Reading goes through the
+// paste command, without one the editor falls back to its own copy of the register.
+// YIM_COPY_CMD and YIM_PASTE_CMD override the detected commands, they run through sh.
+
+// Terminals drop OSC 52 payloads past roughly this size
+const OSC52_LIMIT: usize = 100_000;
+
+#[derive(Debug, Default)]
+pub struct Clipboard {
+    pub copy_command: Option<String>,
+    pub paste_command: Option<String>,
+    pub osc52: bool,
+}
+
+impl Clipboard {
+    pub fn detect() -> Self {
+        let (copy, paste) = Self::detect_commands();
+        Self {
+            copy_command: env::var("YIM_COPY_CMD").ok().or(copy),
+            paste_command: env::var("YIM_PASTE_CMD").ok().or(paste),
+            osc52: true,
+        }
+    }
+    fn detect_commands() -> (Option<String>, Option<String>) {
+        let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+        let x11 = env::var_os("DISPLAY").is_some();
+        let candidates = [
+            (wayland, "wl-copy", "wl-copy", "wl-paste --no-newline"),
+            (
+                x11,
+                "xclip",
+                "xclip -selection clipboard",
+                "xclip -selection clipboard -o",
+            ),
+            (
+                x11,
+                "xsel",
+                "xsel --clipboard --input",
+                "xsel --clipboard --output",
+            ),
+            (true, "pbcopy", "pbcopy", "pbpaste"),
+        ];
+        for (usable, program, copy, paste) in candidates {
+            if usable && Self::in_path(program) {
+                return (Some(copy.to_string()), Some(paste.to_string()));
+            }
+        }
+        (None, None)
+    }
+    fn in_path(program: &str) -> bool {
+        env::var_os("PATH")
+            .map(|paths| {
+                env::split_paths(&paths).any(|dir| Path::new(&dir).join(program).is_file())
+            })
+            .unwrap_or(false)
+    }
+    // "* is the X11 primary selection, "+ the regular clipboard
+    fn for_selection(command: &str, register: char) -> String {
+        if register != '*' {
+            return command.to_string();
+        }
+        command
+            .replace("-selection clipboard", "-selection primary")
+            .replace("--clipboard", "--primary")
+            .replacen("wl-copy", "wl-copy --primary", 1)
+            .replacen("wl-paste", "wl-paste --primary", 1)
+    }
+    pub(crate) fn copy(&self, terminal: &Rc<RefCell<Terminal>>, register: char, text: &[u8]) {
+        if self.osc52 && text.len() <= OSC52_LIMIT {
+            let target = if register == '*' { 'p' } else { 'c' };
+            let sequence = format!("\x1B]52;{};{}\x07", target, base64(text));
+            terminal.borrow_mut().write(sequence.as_bytes());
+        }
+        let command = match &self.copy_command {
+            Some(c) => Self::for_selection(c, register),
+            None => return,
+        };
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let result = child.and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(text)?;
+            }
+            child.wait()
+        });
+        if let Err(err) = result {
+            log::error!("clipboard copy through {:?} failed: {}", command, err);
+        }
+    }
+    // None when there is no paste command or it failed
+    pub(crate) fn paste(&self, register: char) -> Option<Vec<u8>> {
+        let command = Self::for_selection(self.paste_command.as_ref()?, register);
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output();
+        match output {
+            Ok(out) if out.status.success() => Some(out.stdout),
+            Ok(out) => {
+                log::error!(
+                    "clipboard paste through {:?} exited {}",
+                    command,
+                    out.status
+                );
+                None
+            }
+            Err(err) => {
+                log::error!("clipboard paste through {:?} failed: {}", command, err);
+                None
+            }
+        }
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::temp_dir;
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar\n"), "Zm9vYmFyCg==");
+    }
+
+    #[test]
+    fn star_register_uses_the_primary_selection() {
+        let copy = "xclip -selection clipboard";
+        assert_eq!(Clipboard::for_selection(copy, '+'), copy);
+        assert_eq!(
+            Clipboard::for_selection(copy, '*'),
+            "xclip -selection primary"
+        );
+        assert_eq!(
+            Clipboard::for_selection("wl-paste --no-newline", '*'),
+            "wl-paste --primary --no-newline"
+        );
+    }
+
+    #[test]
+    fn copy_and_paste_go_through_the_commands() {
+        let dir = temp_dir("clipboard");
+        let file = dir.join("clip");
+        let clipboard = Clipboard {
+            copy_command: Some(format!("cat > {}", file.display())),
+            paste_command: Some(format!("cat {}", file.display())),
+            osc52: false,
+        };
+        let terminal = Terminal::new(std::io::stdin(), std::io::stdout());
+        clipboard.copy(&terminal, '+', b"copied\n");
+        assert_eq!(clipboard.paste('+'), Some(b"copied\n".to_vec()));
+    }
+
+    #[test]
+    fn failing_paste_command_gives_nothing() {
+        let clipboard = Clipboard {
+            copy_command: None,
+            paste_command: Some(String::from("exit 1")),
+            osc52: false,
+        };
+        assert_eq!(clipboard.paste('+'), None);
+        assert_eq!(Clipboard::default().paste('+'), None);
+    }
+}
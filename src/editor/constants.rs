@@ -18,6 +18,21 @@ pub enum EditorHealth {
 pub enum EditorModes {
     Normal,
     Insert,
+    Replace,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    // Typing after : or /
+    CommandLine,
+}
+
+impl EditorModes {
+    pub fn is_visual(&self) -> bool {
+        matches!(
+            self,
+            EditorModes::Visual | EditorModes::VisualLine | EditorModes::VisualBlock
+        )
+    }
 }
 
 pub const Color_Off: &[u8] = b"\x1B[0m"; // Text Reset;
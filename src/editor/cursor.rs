@@ -1,5 +1,4 @@
 use std::cell::RefCell;
-use std::io::Write;
 use std::rc::Rc;
 
 pub use crate::editor::constants::*;
@@ -95,6 +94,16 @@ impl Cursor {
         }
         (index_l as usize, index_r as usize)
     }
+    // Inverse of calculate_file_index, puts the cursor on the given byte of the file
+    pub(crate) fn set_file_index(&mut self, new_lines: &[i32], index: usize) {
+        let y = new_lines.partition_point(|nl| (*nl as usize) < index);
+        let index_l = match y {
+            0 => 0,
+            _ => new_lines[y - 1] as usize + 1,
+        };
+        self.c_y = y;
+        self.c_x = index.saturating_sub(index_l);
+    }
     pub(crate) fn move_cursor(
         &mut self,
         new_lines: &Vec<i32>,
@@ -132,7 +141,7 @@ impl Cursor {
                 if (new_lines.len() as i32) - (offset as i32) > self.c_y as i32 {
                     self.c_y += offset
                 } else {
-                    self.c_y = new_lines.len() - 1
+                    self.c_y = new_lines.len().saturating_sub(1)
                 }
             }
             CursorDirections::Up => {
@@ -147,7 +156,7 @@ impl Cursor {
                     self.c_x += offset
                 } else if row_insert_size != 0
                     && self.c_x >= row_insert_size - offset + 1
-                    && self.c_y >= new_lines.len() - offset + self.row_offset
+                    && self.c_y + offset >= new_lines.len() + self.row_offset
                 {
                     self.c_y += offset;
                     self.c_x = 0;
@@ -164,78 +173,6 @@ impl Cursor {
         }
         Ok(())
     }
-    pub(crate) fn naive_move_cursor(
-        &self,
-        terminal: &Rc<RefCell<Terminal>>,
-        direction: CursorDirections,
-        offset: usize,
-    ) {
-        // Does not calculate borders
-        match direction {
-            CursorDirections::LineBegin | CursorDirections::LineEnd => !unimplemented!(),
-            CursorDirections::Up => {
-                if terminal
-                    .borrow_mut()
-                    .stdout
-                    .write(format!("\x1B[{}A", offset).as_bytes())
-                    .unwrap() as u32
-                    != 3
-                {
-                    log::error!("Couldn't go to command mode");
-                }
-            }
-            CursorDirections::Down => {
-                if terminal
-                    .borrow_mut()
-                    .stdout
-                    .write(format!("\x1B[{}B", offset).as_bytes())
-                    .unwrap() as u32
-                    != 3
-                {
-                    log::error!("Couldn't go to command mode");
-                }
-            }
-            CursorDirections::Right => {
-                if terminal
-                    .borrow_mut()
-                    .stdout
-                    .write(format!("\x1B[{}C", offset).as_bytes())
-                    .unwrap() as u32
-                    != 3
-                {
-                    log::error!("Couldn't go to command mode");
-                }
-            }
-            CursorDirections::Left => {
-                if terminal
-                    .borrow_mut()
-                    .stdout
-                    .write(format!("\x1B[{}D", offset).as_bytes())
-                    .unwrap() as u32
-                    != 3
-                {
-                    log::error!("Couldn't go to command mode");
-                }
-            }
-        }
-    }
-    pub(crate) fn naive_move_cursor_2d(
-        &self,
-        terminal: &Rc<RefCell<Terminal>>,
-        x: usize,
-        y: usize,
-    ) {
-        // Does not calculate borders
-        if terminal
-            .borrow_mut()
-            .stdout
-            .write(format!("\x1B[{};{}H", x, y).as_bytes())
-            .unwrap() as u32
-            != 5
-        {
-            log::error!("Couldn't go to command mode",);
-        }
-    }
     pub(crate) fn cursor_limits(&self, t: usize, mode: bool) -> usize {
         if t < 0 {
             return 0;
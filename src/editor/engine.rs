@@ -4,24 +4,39 @@ use std::{
     rc::{Rc, Weak},
 };
 
+use crate::editor::constants::EditorHealth;
+use crate::editor::Editor;
+
 pub trait IOperator {
     type OpType;
     fn new(signature: String, function: Self::OpType) -> Rc<RefCell<Self>>;
-    fn run(&self) -> Result<(), ()>;
+    fn run(&self, editor: &mut Editor, count: Option<usize>) -> Option<EditorHealth>;
 }
 
+// A complete change as the user typed it, e.g. `3dj` is signature "d", motion "j", count 3
+// and `ihello<Esc>` is signature "i" with "hello" inserted. Kept around so `.` can replay it.
+pub type OpFn = fn(&mut Editor, &Operator, usize) -> Option<EditorHealth>;
+
 #[derive(Debug, Clone)]
 pub struct Operator {
     pub signature: String,
-    pub ftor: fn() -> Result<(), ()>,
+    pub motion: String,
+    pub count: usize,
+    pub inserted: Vec<u8>,
+    pub register: Option<char>,
+    pub ftor: OpFn,
     //pub reciprocal_to: Option<Weak<RefCell<Operator>>>,
 }
 
 impl IOperator for Operator {
-    type OpType = fn() -> Result<(), ()>;
-    fn new(signature: String, ftor: fn() -> Result<(), ()>) -> Rc<RefCell<Self>> {
+    type OpType = OpFn;
+    fn new(signature: String, ftor: OpFn) -> Rc<RefCell<Self>> {
         Rc::new(RefCell::new(Self {
             signature,
+            motion: String::new(),
+            count: 1,
+            inserted: Vec::new(),
+            register: None,
             ftor,
             //reciprocal_to: None,
         }))
@@ -38,22 +53,263 @@ impl IOperator for Operator {
     //    );
     //}
 
-    fn run(&self) -> Result<(), ()> {
+    // A new count replaces the recorded one, like vim's `3.`
+    fn run(&self, editor: &mut Editor, count: Option<usize>) -> Option<EditorHealth> {
         let x = self.ftor;
-        x()
+        x(editor, self, count.unwrap_or(self.count))
     }
 }
 
 pub mod operations {
     pub mod insert {
         pub use crate::editor::constants::*;
+        use crate::editor::engine::operations::normal::{
+            cursor_offset, first_non_blank, shift_line,
+        };
+        pub use crate::editor::engine::Operator;
         pub use crate::editor::AppendBuffer;
         pub use crate::editor::Cursor;
+        pub use crate::editor::Editor;
+        use crate::editor::EditorContext;
         use std::{
             cell::Ref,
             cell::RefCell,
             rc::{Rc, Weak},
         };
+
+        // Feeds keys typed in insert mode into the buffer. Besides text that is
+        // Backspace/Ctrl-H, Ctrl-W, Ctrl-U, Ctrl-T, Ctrl-D and Ctrl-V quoting the key
+        // after it, so `.` repeats them like they were typed.
+        pub(crate) fn type_keys(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            keys: &[u8],
+        ) {
+            let mut keys = keys.iter();
+            while let Some(k) = keys.next() {
+                match *k {
+                    // Backspace and Ctrl-H
+                    b'\x7F' | b'\x08' => {
+                        remove_char(cursor, data);
+                    }
+                    // Ctrl-W
+                    b'\x17' => remove_word(context, cursor, data),
+                    // Ctrl-U
+                    b'\x15' => remove_line_before(cursor, data),
+                    // Ctrl-T and Ctrl-D
+                    b'\x14' | b'\x04' => shift_insert_line(context, cursor, data, *k == b'\x14'),
+                    // Ctrl-V
+                    b'\x16' => {
+                        if let Some(k) = keys.next() {
+                            insert_literal(cursor, data, *k);
+                        }
+                    }
+                    _ => {
+                        insert_char(cursor, data, *k);
+                    }
+                };
+            }
+        }
+        // Ctrl-V{key}: the key itself even when it is a control character. A quoted
+        // Ctrl-J still breaks the line, the buffer has no other way to hold one.
+        pub(crate) fn insert_literal(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            ch: u8,
+        ) {
+            if ch == b'\n' {
+                insert_char(cursor, data, b'\r');
+                return;
+            }
+            let offset = cursor_offset(cursor, data);
+            data.insert(offset, ch);
+            cursor
+                .borrow_mut()
+                .set_file_index(&data.new_lines, offset + 1);
+        }
+        fn remove_before(cursor: &Rc<RefCell<Cursor>>, data: &mut AppendBuffer, from: usize) {
+            let offset = cursor_offset(cursor, data);
+            data.replace_slice(from..offset, b"");
+            cursor.borrow_mut().set_file_index(&data.new_lines, from);
+        }
+        // Ctrl-W: the blanks before the cursor and the word before them. Punctuation
+        // counts as a word of its own, at the start of a line the line break goes.
+        pub(crate) fn remove_word(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+        ) {
+            let y = cursor.borrow().absy();
+            let (line_begin, _) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let offset = cursor_offset(cursor, data);
+            if offset <= line_begin {
+                remove_char(cursor, data);
+                return;
+            }
+            let before = &data.buffer[line_begin..offset];
+            let c = context.borrow();
+            let is_keyword = |k: u8| c.options.is_keyword(k);
+            let blank = |c: &&u8| **c == b' ' || **c == b'\t';
+            let blanks = before.iter().rev().take_while(blank).count();
+            let rest = &before[..before.len() - blanks];
+            let word = match rest.last() {
+                Some(c) if is_keyword(*c) => {
+                    rest.iter().rev().take_while(|c| is_keyword(**c)).count()
+                }
+                Some(_) => rest
+                    .iter()
+                    .rev()
+                    .take_while(|c| !is_keyword(**c) && !blank(c))
+                    .count(),
+                None => 0,
+            };
+            drop(c);
+            remove_before(cursor, data, offset - blanks - word);
+        }
+        // Ctrl-U: the text before the cursor, keeping the indent unless the cursor is
+        // in it. At the start of a line the line break goes.
+        pub(crate) fn remove_line_before(cursor: &Rc<RefCell<Cursor>>, data: &mut AppendBuffer) {
+            let y = cursor.borrow().absy();
+            let (line_begin, _) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let offset = cursor_offset(cursor, data);
+            if offset <= line_begin {
+                remove_char(cursor, data);
+                return;
+            }
+            let indent = line_begin + first_non_blank(cursor, data, y);
+            remove_before(
+                cursor,
+                data,
+                if offset > indent { indent } else { line_begin },
+            );
+        }
+        // Ctrl-T and Ctrl-D shift the line by a shift width, the cursor stays on the
+        // same character
+        pub(crate) fn shift_insert_line(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            right: bool,
+        ) {
+            let y = cursor.borrow().absy();
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let column = cursor_offset(cursor, data) - line_begin;
+            let indent = first_non_blank(cursor, data, y);
+            let shift_width = context.borrow().options.shift_width();
+            shift_line(cursor, data, y, right, 1, shift_width);
+            let (new_begin, new_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let grown = (new_end - new_begin) as isize - (line_end - line_begin) as isize;
+            // Inside the indent the cursor moves to where the text starts
+            let column = match column < indent {
+                true => (indent as isize + grown) as usize,
+                false => (column as isize + grown) as usize,
+            };
+            cursor
+                .borrow_mut()
+                .set_file_index(&data.new_lines, new_begin + column);
+        }
+        pub(crate) fn replay_insert(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            editor.position_for_insert(op.signature.as_bytes()[0]);
+            for _ in 0..count {
+                type_keys(
+                    &editor.context,
+                    &editor.cursor,
+                    &mut editor.data,
+                    &op.inserted,
+                );
+            }
+            editor.context.borrow_mut().dirty = 1;
+            Some(EditorHealth::Healthy)
+        }
+        // Replace mode typing: overwrites the byte under the cursor, appends past the end
+        // of the line and breaks the line on Enter. Returns the overwritten byte, None when
+        // nothing was overwritten, so backspace can undo it.
+        pub(crate) fn replace_char(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            ch: u8,
+        ) -> Option<u8> {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let (_, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+            if ch == 13 || ind >= line_end {
+                insert_char(cursor, data, ch);
+                return None;
+            }
+            let original = data.buffer[ind];
+            data.replace_slice(ind..ind + 1, &[ch]);
+            cursor.borrow_mut().up_x(1);
+            Some(original)
+        }
+        // Backspace in replace mode, puts back what replace_char overwrote
+        pub(crate) fn restore_char(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            original: Option<u8>,
+        ) {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+            if ind == 0 {
+                return;
+            }
+            match original {
+                Some(c) => data.replace_slice(ind - 1..ind, &[c]),
+                None => {
+                    data.remove(ind - 1);
+                    data.update_buffers();
+                }
+            }
+            cursor.borrow_mut().set_file_index(&data.new_lines, ind - 1);
+        }
+        // Same as type_keys but overwriting, for repeating R
+        pub(crate) fn overtype_keys(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            keys: &[u8],
+        ) {
+            let mut backup = Vec::new();
+            for k in keys {
+                match *k {
+                    b'\x7F' => match backup.pop() {
+                        Some(original) => restore_char(cursor, data, original),
+                        None => {
+                            let _ = cursor.borrow_mut().move_cursor(
+                                &data.new_lines,
+                                CursorDirections::Left,
+                                1,
+                            );
+                        }
+                    },
+                    _ => backup.push(replace_char(cursor, data, *k)),
+                }
+            }
+        }
+        pub(crate) fn replay_overtype(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            for _ in 0..count {
+                overtype_keys(&editor.cursor, &mut editor.data, &op.inserted);
+            }
+            editor.context.borrow_mut().dirty = 1;
+            Some(EditorHealth::Healthy)
+        }
         pub(crate) fn remove_char(
             cursor: &Rc<RefCell<Cursor>>,
             data: &mut AppendBuffer,
@@ -108,7 +364,6 @@ pub mod operations {
     }
     pub mod normal {
         pub use crate::editor::constants::*;
-        pub use crate::editor::graphics::*;
         pub use crate::editor::AppendBuffer;
         pub use crate::editor::Cursor;
         pub use crate::editor::Terminal;
@@ -118,56 +373,1076 @@ pub mod operations {
             cell::RefCell,
             rc::{Rc, Weak},
         };
-        pub(crate) fn delete_operations(
+        // d{motion} once the motion is known; x, X and D are dl, dh and d$
+        pub(crate) fn delete_motion(
             context: &Rc<RefCell<EditorContext>>,
             cursor: &Rc<RefCell<Cursor>>,
             terminal: &Rc<RefCell<Terminal>>,
             data: &mut AppendBuffer,
-            k: u8,
+            motion: &str,
         ) -> Option<EditorHealth> {
-            let mut x = [0u8; 4];
-            let mut cmd = String::new();
-            cmd.push(k as char);
-            loop {
-                if cmd.len() == 2 {
+            let count = context.borrow().count();
+            if apply_delete(context, cursor, terminal, data, motion, count) {
+                let op = Operator::new(String::from("d"), replay_delete);
+                op.borrow_mut().motion = motion.to_string();
+                op.borrow_mut().count = count;
+                op.borrow_mut().register = context.borrow().registers.selected;
+                context.borrow_mut().last_change = Some(op);
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // Returns false when the motion is unknown so nothing gets recorded for `.`
+        pub(crate) fn apply_delete(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &mut AppendBuffer,
+            motion: &str,
+            count: usize,
+        ) -> bool {
+            if motion == "$" {
+                return delete_to_line_end(context, cursor, terminal, data, count);
+            }
+            let (direction, times) = match motion {
+                "d" => (None, count),
+                // dj and dk take the current line along, so 2dj removes 3 lines
+                "j" => (None, count + 1),
+                "k" => {
+                    cursor
+                        .borrow_mut()
+                        .move_cursor(&data.new_lines, CursorDirections::Up, count)
+                        .unwrap();
+                    (None, count + 1)
+                }
+                "l" => (Some(CursorDirections::Right), count),
+                "h" => (Some(CursorDirections::Left), count),
+                _ => return false,
+            };
+            let mut removed = Vec::new();
+            for _ in 0..times {
+                if data.new_lines.is_empty() {
                     break;
                 }
-                let key = terminal.borrow_mut().read_key().unwrap();
-                if key == 27 as u8 {
+                match direction {
+                    None => removed.extend(delete_line(context, cursor, terminal, data)),
+                    Some(CursorDirections::Left) if cursor.borrow().absx() == 0 => break,
+                    Some(CursorDirections::Left) => {
+                        let mut c = delete(context, cursor, terminal, data, CursorDirections::Left);
+                        c.extend(removed);
+                        removed = c;
+                    }
+                    Some(_) => removed.extend(delete(
+                        context,
+                        cursor,
+                        terminal,
+                        data,
+                        CursorDirections::Right,
+                    )),
+                }
+            }
+            if !removed.is_empty() {
+                let kind = match direction {
+                    None => RegisterKind::Linewise,
+                    Some(_) => RegisterKind::Charwise,
+                };
+                store_register(context, terminal, RegisterOp::Delete, kind, removed);
+            }
+            let lines = data.new_lines.len();
+            if lines > 0 && cursor.borrow().absy() >= lines {
+                cursor.borrow_mut().set_y(lines - 1);
+            }
+            true
+        }
+        // D, from the cursor to the end of the line, a count takes count - 1 more lines
+        pub(crate) fn delete_to_line_end(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &mut AppendBuffer,
+            count: usize,
+        ) -> bool {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            if y >= data.new_lines.len() {
+                return false;
+            }
+            let last = (y + count - 1).min(data.new_lines.len() - 1);
+            let (_, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(last, &data.new_lines);
+            let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+            if ind >= line_end {
+                return false;
+            }
+            let removed = data.buffer[ind..line_end].to_vec();
+            data.replace_slice(ind..line_end, b"");
+            store_register(
+                context,
+                terminal,
+                RegisterOp::Delete,
+                RegisterKind::Charwise,
+                removed,
+            );
+            cursor.borrow_mut().set_x(x.saturating_sub(1));
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        // The deleting half of c{motion}, C, S and s. Whole lines keep the indent of
+        // the first one so the text typed afterwards lands where the old text started.
+        pub(crate) fn apply_change(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &mut AppendBuffer,
+            motion: &str,
+            count: usize,
+        ) -> bool {
+            let y = cursor.borrow().absy();
+            let lines = match motion {
+                "c" => count,
+                "j" => count + 1,
+                "k" => {
+                    let up = count.min(y);
+                    cursor.borrow_mut().set_y(y - up);
+                    up + 1
+                }
+                "$" => {
+                    // Unlike D the cursor stays where the removed text began
+                    let x = cursor.borrow().absx();
+                    delete_to_line_end(context, cursor, terminal, data, count);
+                    cursor.borrow_mut().set_x(x);
+                    return true;
+                }
+                "l" | "h" => {
+                    apply_delete(context, cursor, terminal, data, motion, count);
+                    return true;
+                }
+                _ => return false,
+            };
+            if data.new_lines.is_empty() {
+                return true;
+            }
+            let first = cursor.borrow().absy();
+            let last = (first + lines - 1).min(data.new_lines.len() - 1);
+            let (line_begin, _) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(first, &data.new_lines);
+            let (_, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(last, &data.new_lines);
+            let indent = indent_of(cursor, data, first);
+            let removed = data.buffer[line_begin..line_end + 1].to_vec();
+            data.replace_slice(line_begin + indent..line_end, b"");
+            store_register(
+                context,
+                terminal,
+                RegisterOp::Delete,
+                RegisterKind::Linewise,
+                removed,
+            );
+            cursor.borrow_mut().set_x(indent);
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        pub(crate) fn replay_change(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            apply_change(
+                &editor.context,
+                &editor.cursor,
+                &editor.terminal,
+                &mut editor.data,
+                &op.motion,
+                count,
+            );
+            operations::insert::type_keys(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                &op.inserted,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        // Length of the leading blanks of line y, whole line when it is only blanks
+        pub(crate) fn indent_of(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &AppendBuffer,
+            y: usize,
+        ) -> usize {
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            data.buffer[line_begin..line_end]
+                .iter()
+                .take_while(|c| **c == b' ' || **c == b'\t')
+                .count()
+        }
+        // o and O, opens a line below or above the cursor with the same indent
+        pub(crate) fn open_line(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            below: bool,
+        ) {
+            let y = cursor.borrow().absy();
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let indent = data.buffer[line_begin..line_begin + indent_of(cursor, data, y)].to_vec();
+            if below && !data.new_lines.is_empty() {
+                let mut line = vec![b'\n'];
+                line.extend(&indent);
+                data.insert_slice(line_end, &line);
+                cursor.borrow_mut().set_y(y + 1);
+            } else {
+                let mut line = indent.clone();
+                line.push(b'\n');
+                data.insert_slice(line_begin, &line);
+            }
+            cursor.borrow_mut().set_x(indent.len());
+            context.borrow_mut().dirty = 1;
+        }
+        pub(crate) fn replay_open_line(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            // 3O types three lines above, each one below the previous
+            let mut below = op.signature == "o";
+            for _ in 0..count {
+                open_line(&editor.context, &editor.cursor, &mut editor.data, below);
+                operations::insert::type_keys(
+                    &editor.context,
+                    &editor.cursor,
+                    &mut editor.data,
+                    &op.inserted,
+                );
+                below = true;
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // J and gJ, count is the number of lines joined like in vim
+        pub(crate) fn replay_join(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            let y = editor.cursor.borrow().absy();
+            join_lines(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                y,
+                count,
+                op.signature == "J",
+            );
+            Some(EditorHealth::Healthy)
+        }
+        pub(crate) fn replay_delete(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            apply_delete(
+                &editor.context,
+                &editor.cursor,
+                &editor.terminal,
+                &mut editor.data,
+                &op.motion,
+                count,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        pub(crate) fn yank_operations(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &AppendBuffer,
+            motion: u8,
+        ) -> Option<EditorHealth> {
+            let count = context.borrow().count();
+            apply_yank(
+                context,
+                cursor,
+                terminal,
+                data,
+                &(motion as char).to_string(),
+                count,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        pub(crate) fn apply_yank(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &AppendBuffer,
+            motion: &str,
+            count: usize,
+        ) -> bool {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let lines = data.new_lines.len();
+            let (kind, begin, end) = match motion {
+                "y" | "j" | "k" if lines > 0 => {
+                    let (first, last) = match motion {
+                        "y" => (y, y + count - 1),
+                        "j" => (y, y + count),
+                        _ => (y.saturating_sub(count), y),
+                    };
+                    let (begin, _) = cursor
+                        .borrow()
+                        .calculate_row_of_insert_indices(first, &data.new_lines);
+                    let (_, end) = cursor
+                        .borrow()
+                        .calculate_row_of_insert_indices(last.min(lines - 1), &data.new_lines);
+                    // yk leaves the cursor on the top line
+                    cursor.borrow_mut().set_y(first);
+                    (RegisterKind::Linewise, begin, end + 1)
+                }
+                "l" => {
+                    let (_, line_end) = cursor
+                        .borrow()
+                        .calculate_row_of_insert_indices(y, &data.new_lines);
+                    let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+                    (RegisterKind::Charwise, ind, (ind + count).min(line_end))
+                }
+                "h" => {
+                    let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+                    let n = count.min(x);
+                    cursor.borrow_mut().set_x(x - n);
+                    (RegisterKind::Charwise, ind - n, ind)
+                }
+                _ => return false,
+            };
+            if begin >= end {
+                return false;
+            }
+            let text = data.buffer[begin..end].to_vec();
+            let yanked_lines = text.iter().filter(|c| **c == b'\n').count();
+            store_register(context, terminal, RegisterOp::Yank, kind, text);
+            let last = match kind {
+                RegisterKind::Linewise => end.saturating_sub(2).max(begin),
+                _ => end - 1,
+            };
+            set_change_marks(context, begin, last);
+            if kind == RegisterKind::Linewise && yanked_lines > 2 {
+                context.borrow_mut().status_message = format!("{} lines yanked", yanked_lines);
+            }
+            true
+        }
+        // Stores into the selected register, "+ and "* also go to the system clipboard
+        pub(crate) fn store_register(
+            context: &Rc<RefCell<EditorContext>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            op: RegisterOp,
+            kind: RegisterKind,
+            text: Vec<u8>,
+        ) {
+            let mut c = context.borrow_mut();
+            if let Some(name @ ('+' | '*')) = c.registers.selected {
+                c.clipboard.copy(terminal, name, &text);
+            }
+            c.registers.store(op, kind, text);
+        }
+        // Contents of the selected register, "+ and "* prefer what the system clipboard holds
+        pub(crate) fn fetch_register(context: &Rc<RefCell<EditorContext>>) -> Option<Register> {
+            let c = context.borrow();
+            let name = c.registers.selected;
+            let stored = c.registers.get(name, &c.files);
+            if let Some(name @ ('+' | '*')) = name {
+                let text = c.clipboard.paste(name).filter(|t| !t.is_empty());
+                if let Some(text) = text {
+                    if stored.as_ref().is_none_or(|r| r.text != text) {
+                        let kind = match text.ends_with(b"\n") {
+                            true => RegisterKind::Linewise,
+                            false => RegisterKind::Charwise,
+                        };
+                        return Some(Register::new(kind, text));
+                    }
+                }
+            }
+            stored
+        }
+        pub(crate) fn put_operations(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            signature: &str,
+        ) -> Option<EditorHealth> {
+            let count = context.borrow().count();
+            if apply_put(context, cursor, data, signature, count) {
+                let op = Operator::new(signature.to_string(), replay_put);
+                op.borrow_mut().count = count;
+                op.borrow_mut().register = context.borrow().registers.selected;
+                context.borrow_mut().last_change = Some(op);
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // p/P put after/before the cursor, or below/above the line for linewise text.
+        // gp/gP do the same but leave the cursor just past the new text.
+        pub(crate) fn apply_put(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            signature: &str,
+            count: usize,
+        ) -> bool {
+            let register = match fetch_register(context) {
+                Some(r) => r,
+                None => {
+                    context.borrow_mut().status_message = String::from("Nothing in register");
+                    return false;
+                }
+            };
+            let after = signature.ends_with('p');
+            let stay = signature.starts_with('g');
+            let text = register.text.repeat(count);
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            match register.kind {
+                RegisterKind::Blockwise => {
+                    let mut column = x;
+                    if after && line_end > line_begin {
+                        column += 1;
+                    }
+                    put_block(cursor, data, &register.text, column, y, count);
+                    cursor.borrow_mut().set_y(y);
+                    cursor.borrow_mut().set_x(column);
+                }
+                RegisterKind::Linewise => {
+                    let index = match (data.new_lines.is_empty(), after) {
+                        (true, true) => data.buffer.len(),
+                        (true, false) => 0,
+                        (false, true) => line_end + 1,
+                        (false, false) => line_begin,
+                    };
+                    data.insert_slice(index, &text);
+                    if stay {
+                        cursor
+                            .borrow_mut()
+                            .set_file_index(&data.new_lines, index + text.len());
+                        let lines = data.new_lines.len();
+                        if cursor.borrow().absy() >= lines {
+                            cursor.borrow_mut().set_y(lines.saturating_sub(1));
+                        }
+                    } else {
+                        cursor.borrow_mut().set_file_index(&data.new_lines, index);
+                        let first = first_non_blank(cursor, data, cursor.borrow().absy());
+                        cursor.borrow_mut().set_x(first);
+                    }
+                }
+                _ => {
+                    let mut index = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+                    if after && line_end > line_begin {
+                        index = (index + 1).min(line_end);
+                    }
+                    data.insert_slice(index, &text);
+                    let end = match stay {
+                        true => index + text.len(),
+                        false => index + text.len() - 1,
+                    };
+                    cursor.borrow_mut().set_file_index(&data.new_lines, end);
+                }
+            }
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        // Pastes each row of a block on its own line starting at `column`, padding
+        // short lines with spaces and adding lines at the end when needed
+        pub(crate) fn put_block(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            text: &[u8],
+            column: usize,
+            y: usize,
+            count: usize,
+        ) {
+            let rows: Vec<&[u8]> = text.split(|c| *c == b'\n').collect();
+            let width = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+            for (i, row) in rows.iter().enumerate() {
+                while y + i >= data.new_lines.len() {
+                    let end = data.buffer.len();
+                    data.insert_slice(end, b"\n");
+                }
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y + i, &data.new_lines);
+                let mut piece = Vec::new();
+                if line_end - line_begin < column {
+                    piece.resize(column - (line_end - line_begin), b' ');
+                }
+                for n in 0..count {
+                    piece.extend_from_slice(row);
+                    // Keep the columns of later copies and of the text after the block
+                    if n + 1 < count || line_begin + column < line_end {
+                        piece.resize(piece.len() + width - row.len(), b' ');
+                    }
+                }
+                let at = (line_begin + column).min(line_end);
+                data.insert_slice(at, &piece);
+            }
+        }
+        pub(crate) fn replay_put(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            apply_put(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                &op.signature,
+                count,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        // Joins `lines` lines starting at y into one. With `spaces` the leading blanks of
+        // every joined line collapse into a single space, like J; without it the text is
+        // glued as it is, like gJ. The cursor ends on the last join point.
+        pub(crate) fn join_lines(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            y: usize,
+            lines: usize,
+            spaces: bool,
+        ) -> bool {
+            let mut joined = false;
+            for _ in 1..lines.max(2) {
+                if y + 1 >= data.new_lines.len() {
                     break;
                 }
-                cmd.push(key as char);
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, &data.new_lines);
+                let (next_begin, next_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y + 1, &data.new_lines);
+                let mut glue: &[u8] = b"";
+                let mut cut = next_begin;
+                if spaces {
+                    cut += data.buffer[next_begin..next_end]
+                        .iter()
+                        .take_while(|c| **c == b' ' || **c == b'\t')
+                        .count();
+                    let ends_blank =
+                        line_end == line_begin || matches!(data.buffer[line_end - 1], b' ' | b'\t');
+                    if !ends_blank && cut < next_end && data.buffer[cut] != b')' {
+                        glue = b" ";
+                    }
+                }
+                data.replace_slice(line_end..cut, glue);
+                cursor
+                    .borrow_mut()
+                    .set_file_index(&data.new_lines, line_end);
+                joined = true;
             }
-
-            let _ = match cmd.as_str() {
-                "dd" => delete_line(context, cursor, terminal, data),
-                "dk" => delete(context, cursor, terminal, data, CursorDirections::Up),
-                "dj" => delete(context, cursor, terminal, data, CursorDirections::Down),
-                "dl" => delete(context, cursor, terminal, data, CursorDirections::Right),
-                "dh" => delete(context, cursor, terminal, data, CursorDirections::Left),
+            if joined {
+                context.borrow_mut().dirty = 1;
+            }
+            joined
+        }
+        // ~ swaps case, u lowers and U uppers. Works on chars, so non ASCII letters
+        // map too and the byte length may change (ß becomes SS).
+        pub(crate) fn change_case(text: &[u8], how: u8) -> Vec<u8> {
+            let map = |c: char| -> String {
+                match how {
+                    b'u' => c.to_lowercase().collect(),
+                    b'U' => c.to_uppercase().collect(),
+                    _ if c.is_uppercase() => c.to_lowercase().collect(),
+                    _ => c.to_uppercase().collect(),
+                }
+            };
+            match std::str::from_utf8(text) {
+                Ok(s) => s.chars().map(map).collect::<String>().into_bytes(),
+                // Not UTF-8, leave everything but ASCII alone
+                Err(_) => text
+                    .iter()
+                    .map(|c| match how {
+                        b'u' => c.to_ascii_lowercase(),
+                        b'U' => c.to_ascii_uppercase(),
+                        _ if c.is_ascii_uppercase() => c.to_ascii_lowercase(),
+                        _ => c.to_ascii_uppercase(),
+                    })
+                    .collect(),
+            }
+        }
+        // Indents (or dedents) lines first..=last by `times` shift widths. Empty lines are
+        // left alone when indenting, a tab counts as a full shift width when dedenting.
+        pub(crate) fn shift_lines(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            first: usize,
+            last: usize,
+            right: bool,
+            times: usize,
+        ) {
+            let shift_width = context.borrow().options.shift_width();
+            for y in (first..=last.min(data.new_lines.len().saturating_sub(1))).rev() {
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, &data.new_lines);
+                if right && line_end == line_begin {
+                    continue;
+                }
+                shift_line(cursor, data, y, right, times, shift_width);
+            }
+            cursor.borrow_mut().set_y(first);
+            let x = first_non_blank(cursor, data, first);
+            cursor.borrow_mut().set_x(x);
+            context.borrow_mut().dirty = 1;
+        }
+        // Adds `times` shift widths of indent to line y or takes up to that many away
+        pub(crate) fn shift_line(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            y: usize,
+            right: bool,
+            times: usize,
+            shift_width: usize,
+        ) {
+            let width = shift_width * times;
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            if right {
+                data.insert_slice(line_begin, " ".repeat(width).as_bytes());
+                return;
+            }
+            let mut removed = 0;
+            let mut cut = line_begin;
+            while cut < line_end && removed < width {
+                match data.buffer[cut] {
+                    b' ' => removed += 1,
+                    b'\t' => removed += shift_width,
+                    _ => break,
+                }
+                cut += 1;
+            }
+            data.remove_slice(line_begin..cut);
+            data.update_buffers();
+        }
+        // r and gr, replace the character under the cursor
+        pub(crate) fn replace_operations(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            signature: &str,
+            ch: u8,
+        ) -> Option<EditorHealth> {
+            let count = context.borrow().count();
+            if apply_replace(context, cursor, data, signature, ch, count) {
+                let op = Operator::new(signature.to_string(), replay_replace);
+                op.borrow_mut().count = count;
+                op.borrow_mut().inserted = vec![ch];
+                context.borrow_mut().last_change = Some(op);
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // Replaces `count` characters with `ch`, nothing happens when the line is too
+        // short. Enter swaps them all for a single line break. gr works on screen cells,
+        // so a tab wider than one cell stays and the character goes in front of it.
+        pub(crate) fn apply_replace(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            signature: &str,
+            ch: u8,
+            count: usize,
+        ) -> bool {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let ind = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+            if ind + count > line_end {
+                return false;
+            }
+            if ch == 13 {
+                data.replace_slice(ind..ind + count, b"\n");
+                cursor.borrow_mut().set_file_index(&data.new_lines, ind + 1);
+            } else if signature == "gr" {
+                let mut at = ind;
+                for _ in 0..count {
+                    let tab_stop = context.borrow().options.tab_stop();
+                    let column = display_column(&data.buffer[line_begin..at], tab_stop);
+                    if data.buffer[at] == b'\t' && tab_stop - column % tab_stop > 1 {
+                        data.insert_slice(at, &[ch]);
+                    } else {
+                        data.replace_slice(at..at + 1, &[ch]);
+                    }
+                    at += 1;
+                }
+                cursor.borrow_mut().set_file_index(&data.new_lines, at - 1);
+            } else {
+                data.replace_slice(ind..ind + count, &vec![ch; count]);
+                cursor
+                    .borrow_mut()
+                    .set_file_index(&data.new_lines, ind + count - 1);
+            }
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        pub(crate) fn replay_replace(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            apply_replace(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                &op.signature,
+                op.inserted[0],
+                count,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        // ~, g~, gu and gU. `how` is the last key of the signature, what change_case takes.
+        pub(crate) fn case_operations(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            signature: &str,
+            motion: &str,
+        ) -> Option<EditorHealth> {
+            let count = context.borrow().count();
+            let how = *signature.as_bytes().last().unwrap();
+            if apply_case(context, cursor, data, how, motion, count) {
+                let op = Operator::new(signature.to_string(), replay_case);
+                op.borrow_mut().motion = motion.to_string();
+                op.borrow_mut().count = count;
+                context.borrow_mut().last_change = Some(op);
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // Motions are the ones d takes, "line" for the doubled forms (g~~, guu, gUU) and
+        // "~" for the plain ~ which, unlike g~l, moves past what it changed.
+        pub(crate) fn apply_case(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            how: u8,
+            motion: &str,
+            count: usize,
+        ) -> bool {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            if y >= data.new_lines.len() {
+                return false;
+            }
+            let row = |y: usize, new_lines: &Vec<i32>| {
+                cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, new_lines)
+            };
+            let last_line = data.new_lines.len() - 1;
+            let (line_begin, line_end) = row(y, &data.new_lines);
+            let ind = (line_begin + x).min(line_end);
+            let (from, to) = match motion {
+                "line" => (
+                    line_begin,
+                    row((y + count - 1).min(last_line), &data.new_lines).1,
+                ),
+                "j" => (
+                    line_begin,
+                    row((y + count).min(last_line), &data.new_lines).1,
+                ),
+                "k" => (row(y.saturating_sub(count), &data.new_lines).0, line_end),
+                "$" => (ind, row((y + count - 1).min(last_line), &data.new_lines).1),
+                "l" | "~" => {
+                    let mut to = ind;
+                    for _ in 0..count {
+                        if to >= line_end {
+                            break;
+                        }
+                        to += utf8_len(data.buffer[to]).min(line_end - to);
+                    }
+                    (ind, to)
+                }
+                "h" => {
+                    let mut from = ind;
+                    for _ in 0..count {
+                        if from == line_begin {
+                            break;
+                        }
+                        from -= 1;
+                        while from > line_begin && data.buffer[from] & 0xC0 == 0x80 {
+                            from -= 1;
+                        }
+                    }
+                    (from, ind)
+                }
+                _ => return false,
+            };
+            if from >= to {
+                return false;
+            }
+            let mapped = change_case(&data.buffer[from..to], how);
+            let mapped_len = mapped.len();
+            data.replace_slice(from..to, &mapped);
+            match motion {
+                "k" | "h" => cursor.borrow_mut().set_file_index(&data.new_lines, from),
+                "~" => {
+                    // Onto the next character, or stay on the last one of the line
+                    let (_, line_end) = row(y, &data.new_lines);
+                    let mut at = from + mapped_len;
+                    if at >= line_end {
+                        at = line_end.saturating_sub(1).max(line_begin);
+                        while at > line_begin && data.buffer[at] & 0xC0 == 0x80 {
+                            at -= 1;
+                        }
+                    }
+                    cursor.borrow_mut().set_file_index(&data.new_lines, at);
+                }
                 _ => (),
+            }
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        pub(crate) fn replay_case(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            let how = *op.signature.as_bytes().last().unwrap();
+            apply_case(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                how,
+                &op.motion,
+                count,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        // Length of the UTF-8 sequence a byte starts, 1 for anything that doesn't start one
+        pub(crate) fn utf8_len(b: u8) -> usize {
+            match b {
+                0xF0..=0xF7 => 4,
+                0xE0..=0xEF => 3,
+                0xC0..=0xDF => 2,
+                _ => 1,
+            }
+        }
+        // A number as Ctrl-A sees it: the bytes it spans on the line (prefix and minus
+        // sign included) and its radix
+        #[derive(Debug, Clone, Copy)]
+        pub struct NumberSpan {
+            pub start: usize,
+            pub end: usize,
+            pub radix: u32,
+        }
+        // The first number ending after column `from`, like vim the cursor may sit before
+        // it or anywhere on it. 0x and 0b prefix hex and binary, a leading 0 followed by
+        // octal digits only makes it octal, a - right before a decimal makes it negative.
+        pub(crate) fn find_number(line: &[u8], from: usize) -> Option<NumberSpan> {
+            let run = |at: usize, f: fn(&u8) -> bool| {
+                at + line[at.min(line.len())..]
+                    .iter()
+                    .take_while(|c| f(c))
+                    .count()
             };
-
+            let mut i = 0;
+            while i < line.len() {
+                if !line[i].is_ascii_digit() {
+                    i += 1;
+                    continue;
+                }
+                let next = line.get(i + 1).copied().unwrap_or(0);
+                let after = line.get(i + 2).copied().unwrap_or(0);
+                let mut span = match (line[i], next) {
+                    (b'0', b'x' | b'X') if after.is_ascii_hexdigit() => NumberSpan {
+                        start: i,
+                        end: run(i + 2, u8::is_ascii_hexdigit),
+                        radix: 16,
+                    },
+                    (b'0', b'b' | b'B') if after == b'0' || after == b'1' => NumberSpan {
+                        start: i,
+                        end: run(i + 2, |c| *c == b'0' || *c == b'1'),
+                        radix: 2,
+                    },
+                    _ => NumberSpan {
+                        start: i,
+                        end: run(i, u8::is_ascii_digit),
+                        radix: 10,
+                    },
+                };
+                let digits = &line[span.start..span.end];
+                if span.radix == 10
+                    && digits.len() > 1
+                    && digits[0] == b'0'
+                    && digits.iter().all(|c| (b'0'..=b'7').contains(c))
+                {
+                    span.radix = 8;
+                }
+                if span.end > from {
+                    if span.radix == 10 && span.start > 0 && line[span.start - 1] == b'-' {
+                        span.start -= 1;
+                    }
+                    return Some(span);
+                }
+                i = span.end;
+            }
+            None
+        }
+        // The text `span` becomes after adding `delta`. Decimals clamp at the i64 limits,
+        // the others wrap around as unsigned and keep their width, prefix and letter case.
+        pub(crate) fn add_to_number(text: &[u8], span: NumberSpan, delta: i64) -> Vec<u8> {
+            let digits = std::str::from_utf8(&text[span.start..span.end]).unwrap_or("0");
+            if span.radix == 10 {
+                let value = digits
+                    .parse::<i128>()
+                    .unwrap_or(match digits.starts_with('-') {
+                        true => i64::MIN as i128,
+                        false => i64::MAX as i128,
+                    });
+                let value = (value + delta as i128).clamp(i64::MIN as i128, i64::MAX as i128);
+                return value.to_string().into_bytes();
+            }
+            let (prefix, body) = match span.radix {
+                8 => ("", digits),
+                _ => digits.split_at(2),
+            };
+            let value = u64::from_str_radix(body, span.radix).unwrap_or(u64::MAX);
+            let value = value.wrapping_add(delta as u64);
+            let width = body.len();
+            let upper = body.bytes().any(|c| c.is_ascii_uppercase())
+                && !body.bytes().any(|c| c.is_ascii_lowercase());
+            let body = match span.radix {
+                2 => format!("{:0width$b}", value),
+                8 => format!("0{:0w$o}", value, w = width.saturating_sub(1)),
+                _ if upper => format!("{:0width$X}", value),
+                _ => format!("{:0width$x}", value),
+            };
+            format!("{}{}", prefix, body).into_bytes()
+        }
+        // Ctrl-A and Ctrl-X
+        pub(crate) fn increment_operations(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            k: u8,
+        ) -> Option<EditorHealth> {
+            let count = context.borrow().count();
+            if apply_increment(context, cursor, data, k, count) {
+                let op = Operator::new((k as char).to_string(), replay_increment);
+                op.borrow_mut().count = count;
+                context.borrow_mut().last_change = Some(op);
+            }
             Some(EditorHealth::Healthy)
         }
+        // Adds count (Ctrl-A) or subtracts it (Ctrl-X) from the number at or after the
+        // cursor, the cursor ends on its last digit. False when the line has no number.
+        pub(crate) fn apply_increment(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            k: u8,
+            count: usize,
+        ) -> bool {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            if y >= data.new_lines.len() {
+                return false;
+            }
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            let line = &data.buffer[line_begin..line_end];
+            let span = match find_number(line, x) {
+                Some(span) => span,
+                None => return false,
+            };
+            let delta = match k {
+                0x18 => -(count as i64),
+                _ => count as i64,
+            };
+            let number = add_to_number(line, span, delta);
+            let at = line_begin + span.start;
+            data.replace_slice(at..line_begin + span.end, &number);
+            cursor
+                .borrow_mut()
+                .set_file_index(&data.new_lines, at + number.len() - 1);
+            context.borrow_mut().dirty = 1;
+            true
+        }
+        pub(crate) fn replay_increment(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            let k = op.signature.as_bytes()[0];
+            apply_increment(&editor.context, &editor.cursor, &mut editor.data, k, count);
+            Some(EditorHealth::Healthy)
+        }
+        // [ and ] around text that was yanked rather than changed
+        pub(crate) fn set_change_marks(
+            context: &Rc<RefCell<EditorContext>>,
+            first: usize,
+            last: usize,
+        ) {
+            let mut c = context.borrow_mut();
+            let file = c.files.clone();
+            c.marks.set('[', first, &file);
+            c.marks.set(']', last, &file);
+        }
+        // Hands the edits made since the last call to the marks, see Marks::apply
+        pub(crate) fn sync_marks(context: &Rc<RefCell<EditorContext>>, data: &mut AppendBuffer) {
+            let edits = std::mem::take(&mut data.edits);
+            let mut c = context.borrow_mut();
+            let inserting = matches!(c.mode, EditorModes::Insert | EditorModes::Replace);
+            let file = c.files.clone();
+            c.marks.apply(&edits, &file, inserting, &data.new_lines);
+        }
+        // Byte offset of the cursor, clamped to the buffer
+        pub(crate) fn cursor_offset(cursor: &Rc<RefCell<Cursor>>, data: &AppendBuffer) -> usize {
+            let (x, y) = (cursor.borrow().absx(), cursor.borrow().absy());
+            let offset = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+            offset.min(data.buffer.len())
+        }
+        // Screen column reached after printing `line`, tabs jump to the next tab stop
+        pub(crate) fn display_column(line: &[u8], tab_stop: usize) -> usize {
+            line.iter().fold(0, |column, c| match c {
+                b'\t' => column + tab_stop - column % tab_stop,
+                _ => column + 1,
+            })
+        }
+        // Column of the first non blank character of line y
+        pub(crate) fn first_non_blank(
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &AppendBuffer,
+            y: usize,
+        ) -> usize {
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(y, &data.new_lines);
+            data.buffer[line_begin..line_end]
+                .iter()
+                .position(|c| *c != b' ' && *c != b'\t')
+                .unwrap_or(0)
+        }
+        // Returns the removed bytes so they can go to a register
         pub(crate) fn delete(
             context: &Rc<RefCell<EditorContext>>,
             cursor: &Rc<RefCell<Cursor>>,
             terminal: &Rc<RefCell<Terminal>>,
             data: &mut AppendBuffer,
             direction: CursorDirections,
-        ) {
+        ) -> Vec<u8> {
+            let mut removed = Vec::new();
             match direction {
                 CursorDirections::Up => {
                     cursor
                         .borrow_mut()
                         .move_cursor(&data.new_lines, CursorDirections::Up, 1);
-                    delete_line(context, cursor, terminal, data);
-                    delete_line(context, cursor, terminal, data);
+                    removed.extend(delete_line(context, cursor, terminal, data));
+                    removed.extend(delete_line(context, cursor, terminal, data));
                 }
                 CursorDirections::Down => {
-                    delete_line(context, cursor, terminal, data);
-                    delete_line(context, cursor, terminal, data);
+                    removed.extend(delete_line(context, cursor, terminal, data));
+                    removed.extend(delete_line(context, cursor, terminal, data));
                 }
                 CursorDirections::Left => {
                     let ind = cursor.borrow().calculate_file_index(
@@ -175,6 +1450,7 @@ pub mod operations {
                         cursor.borrow().absx() as usize,
                         cursor.borrow().absy() as usize,
                     ) - 1;
+                    removed.push(data.buffer[ind]);
                     data.remove(ind);
                     cursor
                         .borrow_mut()
@@ -182,101 +1458,798 @@ pub mod operations {
                     data.update_buffers();
                 }
                 CursorDirections::Right => {
-                    cursor
-                        .borrow_mut()
-                        .move_cursor(&data.new_lines, CursorDirections::Right, 1);
+                    let (_, line_end) = cursor
+                        .borrow()
+                        .calculate_row_of_insert_indices(cursor.borrow().absy(), &data.new_lines);
                     let ind = cursor.borrow().calculate_file_index(
                         &data.new_lines,
                         cursor.borrow().absx() as usize,
                         cursor.borrow().absy() as usize,
-                    ) - 1;
-                    data.remove(ind);
-                    cursor
-                        .borrow_mut()
-                        .move_cursor(&data.new_lines, CursorDirections::Left, 1);
-                    data.update_buffers();
+                    );
+                    // Never eats the newline, the cursor keeps its column
+                    if ind < line_end {
+                        removed.push(data.buffer[ind]);
+                        data.remove(ind);
+                        data.update_buffers();
+                    }
                 }
                 _ => (),
             }
-            //let (index_l, index_r) = self.calculate_row_of_insert_indices(self.c_y as usize);
-            //self.c_x = self.data.buffer[index_l..index_r].len() - 1;
+            context.borrow_mut().dirty = 1;
+            removed
         }
         pub(crate) fn delete_line(
             context: &Rc<RefCell<EditorContext>>,
             cursor: &Rc<RefCell<Cursor>>,
             terminal: &Rc<RefCell<Terminal>>,
             data: &mut AppendBuffer,
-        ) {
-            //let (index_l, index_r) = self.calculate_row_of_insert_indices(self.c_y as usize);
-            //self.c_x = self.data.buffer[index_l..index_r].len() - 1;
-            let mut line_begin = 0;
-            if cursor.borrow().y() == 0 {
-                line_begin = 0;
-            } else {
-                line_begin = data.new_lines[cursor.borrow().y() - 1] as usize;
+        ) -> Vec<u8> {
+            if cursor.borrow().absy() >= data.new_lines.len() {
+                return Vec::new();
             }
-            let mut line_end = data.new_lines[cursor.borrow().y()] as usize;
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(cursor.borrow().absy(), &data.new_lines);
+            // The newline goes too so the next line moves up
+            let line_end = (line_end + 1).min(data.buffer.len());
             log::debug!("Deleting lines {}..{}", line_begin, line_end);
+            let removed = data.buffer[line_begin..line_end].to_vec();
             data.remove_slice(line_begin..line_end);
-            //cursor
-            //    .borrow_mut()
-            //    .move_cursor(&data.new_lines, CursorDirections::Up, 1);
             context.borrow_mut().dirty = 1;
             data.update_buffers();
+            removed
         }
-        pub(crate) fn clear_status_message_from_editor(
-            terminal: &Rc<RefCell<Terminal>>,
-            status_message: &String,
+    }
+    pub mod visual {
+        pub use crate::editor::engine::operations::normal::*;
+        use std::{cell::RefCell, rc::Rc};
+
+        // A pending I, A or c on a block, see finish_block_insert
+        #[derive(Debug, Clone, Copy)]
+        pub struct BlockInsert {
+            pub column: usize,
+            pub first: usize,
+            pub last: usize,
+            pub append: bool,
+            pub to_eol: bool,
+            // Columns c took out of every row, 0 for I and A
+            pub width: usize,
+        }
+        // Corners of the selection as (x, y). Character and line selections are ordered
+        // by position, a block by its top left and bottom right corners.
+        pub(crate) fn visual_bounds(
+            context: &EditorContext,
+            cursor: &Cursor,
+        ) -> ((usize, usize), (usize, usize)) {
+            let anchor = context.visual_start;
+            let head = (cursor.absx(), cursor.absy());
+            match context.mode {
+                EditorModes::VisualBlock => (
+                    (anchor.0.min(head.0), anchor.1.min(head.1)),
+                    (anchor.0.max(head.0), anchor.1.max(head.1)),
+                ),
+                _ if (anchor.1, anchor.0) <= (head.1, head.0) => (anchor, head),
+                _ => (head, anchor),
+            }
+        }
+        // Selected columns of row y, `line_len + 1` standing for the newline
+        pub(crate) fn row_columns(
+            context: &EditorContext,
+            cursor: &Cursor,
+            y: usize,
+            line_len: usize,
+        ) -> Option<(usize, usize)> {
+            let (start, end) = visual_bounds(context, cursor);
+            if y < start.1 || y > end.1 {
+                return None;
+            }
+            let (from, to) = match context.mode {
+                EditorModes::VisualLine => (0, line_len + 1),
+                // After $ every row is selected up to its own end
+                EditorModes::VisualBlock if context.visual_to_eol => {
+                    (start.0.min(line_len), line_len)
+                }
+                EditorModes::VisualBlock => (start.0.min(line_len), (end.0 + 1).min(line_len)),
+                _ => (
+                    if y == start.1 { start.0 } else { 0 },
+                    if y == end.1 { end.0 + 1 } else { line_len + 1 },
+                ),
+            };
+            Some((from.min(line_len + 1), to.min(line_len + 1)))
+        }
+        // Byte ranges covered by the selection, top to bottom. One range for character
+        // and line selections, one per row for blocks.
+        pub(crate) fn selection_ranges(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &AppendBuffer,
+        ) -> Vec<(usize, usize)> {
+            let c = context.borrow();
+            let cur = cursor.borrow();
+            let (start, end) = visual_bounds(&c, &cur);
+            let mut ranges = Vec::new();
+            for y in start.1..=end.1.min(data.new_lines.len().saturating_sub(1)) {
+                let (line_begin, line_end) =
+                    cur.calculate_row_of_insert_indices(y, &data.new_lines);
+                if let Some((from, to)) = row_columns(&c, &cur, y, line_end - line_begin) {
+                    let to = (line_begin + to).min(data.buffer.len());
+                    ranges.push((line_begin + from, to));
+                }
+            }
+            if c.mode != EditorModes::VisualBlock && !ranges.is_empty() {
+                let (first, last) = (ranges[0].0, ranges[ranges.len() - 1].1);
+                ranges = vec![(first, last)];
+            }
+            ranges
+        }
+        pub(crate) fn register_kind(mode: EditorModes) -> RegisterKind {
+            match mode {
+                EditorModes::VisualLine => RegisterKind::Linewise,
+                EditorModes::VisualBlock => RegisterKind::Blockwise,
+                _ => RegisterKind::Charwise,
+            }
+        }
+        pub(crate) fn selected_text(data: &AppendBuffer, ranges: &[(usize, usize)]) -> Vec<u8> {
+            let rows: Vec<&[u8]> = ranges.iter().map(|(l, r)| &data.buffer[*l..*r]).collect();
+            rows.join(&b'\n')
+        }
+        // Remembers the selection for gv and goes back to normal mode
+        pub(crate) fn leave_visual(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &AppendBuffer,
         ) {
-            let status_len: usize = status_message.capacity();
-            let mut cmd_buffer = String::new();
-            cmd_buffer.push(b'/' as char);
-            for _i in 0..status_len {
-                cmd_buffer.push(' ');
+            let (mut start, mut end) = visual_bounds(&context.borrow(), &cursor.borrow());
+            let mut c = context.borrow_mut();
+            if c.mode == EditorModes::VisualLine {
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(end.1, &data.new_lines);
+                start.0 = 0;
+                end.0 = (line_end - line_begin).saturating_sub(1);
             }
-            terminal.borrow_mut().write(cmd_buffer.as_bytes());
+            let file = c.files.clone();
+            for (name, (x, y)) in [('<', start), ('>', end)] {
+                let offset = cursor.borrow().calculate_file_index(&data.new_lines, x, y);
+                c.marks.set(name, offset.min(data.buffer.len()), &file);
+            }
+            let head = (cursor.borrow().absx(), cursor.borrow().absy());
+            c.last_visual = Some((c.mode, c.visual_start, head));
+            c.mode = EditorModes::Normal;
         }
-        pub(crate) fn find_in_file_blocking(
+        // Applies the operator `k` to the selection and leaves visual mode
+        pub(crate) fn visual_operations(
             context: &Rc<RefCell<EditorContext>>,
             cursor: &Rc<RefCell<Cursor>>,
             terminal: &Rc<RefCell<Terminal>>,
-            data: &AppendBuffer,
+            data: &mut AppendBuffer,
+            k: u8,
         ) -> Option<EditorHealth> {
-            //In this mode we show user typed value.
-            //self.terminal.borrow_mut().control_echo(true);
-            // TODO: Hacky render fix alter
-            let mut t_c = cursor.borrow_mut();
-            t_c.naive_move_cursor_2d(&terminal, t_c.rows + 2, 0);
-            clear_status_message_from_editor(terminal, &context.borrow().status_message);
-            let mut word = String::new();
-            t_c.naive_move_cursor_2d(&terminal, t_c.rows + 2, 2);
-            // REFREFREFACTOR
-            loop {
-                let key = terminal.borrow_mut().read_key().unwrap();
-                if key == b'\x7F' {
-                    //BACKSPACE is clicked
-                    // ALL this to have backspace HAHAHA
-                    word.pop();
-                    //t_c.naive_move_cursor(&terminal, CursorDirections::Left, 1);
-                    //terminal.borrow_mut().write(b" ");
-                    //t_c.naive_move_cursor(&terminal, CursorDirections::Left, 1);
-                    continue;
+            let mode = context.borrow().mode;
+            let ranges = selection_ranges(context, cursor, data);
+            let (start, end) = visual_bounds(&context.borrow(), &cursor.borrow());
+            let count = context.borrow().count();
+            let to_eol = context.borrow().visual_to_eol;
+            leave_visual(context, cursor, data);
+            if ranges.is_empty() {
+                return Some(EditorHealth::Healthy);
+            }
+            match k {
+                b'y' => {
+                    let (first, last) = (ranges[0].0, ranges[ranges.len() - 1].1);
+                    set_change_marks(context, first, last.saturating_sub(1).max(first));
+                    let text = selected_text(data, &ranges);
+                    store_register(
+                        context,
+                        terminal,
+                        RegisterOp::Yank,
+                        register_kind(mode),
+                        text,
+                    );
+                    cursor.borrow_mut().set_y(start.1);
+                    cursor.borrow_mut().set_x(start.0);
+                    if mode == EditorModes::VisualLine {
+                        cursor.borrow_mut().set_x(0);
+                    }
                 }
-                if key == 27 as u8 || key == b'\r' {
-                    //Until ENTER is clicked
-                    break;
-                } else {
-                    word.push(key as char);
-                    terminal.borrow_mut().write(&[key]);
-                    context.borrow_mut().highlight_register = data.find(&word);
-                    let mut append_buffer = AppendBuffer::default();
-                    //draw(context, cursor, data, &mut append_buffer);
-                    log::debug!("Found: {:?}", word);
-                    log::debug!("Found: {:?}", context.borrow_mut().highlight_register);
-                    render(context, terminal, &mut t_c, &data, &mut append_buffer);
+                b'd' | b'x' | b'c' | b's' => {
+                    delete_ranges(context, terminal, data, &ranges, mode);
+                    cursor.borrow_mut().set_y(start.1);
+                    cursor.borrow_mut().set_x(start.0);
+                    if mode == EditorModes::VisualLine {
+                        // c keeps an empty line to type on
+                        if k == b'c' || k == b's' {
+                            let (line_begin, _) = cursor
+                                .borrow()
+                                .calculate_row_of_insert_indices(start.1, &data.new_lines);
+                            let at = match start.1 < data.new_lines.len() {
+                                true => line_begin,
+                                false => data.buffer.len(),
+                            };
+                            data.insert_slice(at, b"\n");
+                        }
+                        cursor.borrow_mut().set_x(0);
+                    }
+                    let lines = data.new_lines.len();
+                    if lines > 0 && cursor.borrow().absy() >= lines {
+                        cursor.borrow_mut().set_y(lines - 1);
+                    }
+                    if k == b'c' || k == b's' {
+                        let op =
+                            Operator::new(String::from("i"), operations::insert::replay_insert);
+                        context.borrow_mut().insert_change = Some(op);
+                        context.borrow_mut().mode = EditorModes::Insert;
+                    }
+                    // Changing a block types the new text on every row of it
+                    if mode == EditorModes::VisualBlock && (k == b'c' || k == b's') {
+                        context.borrow_mut().block_insert = Some(BlockInsert {
+                            column: start.0,
+                            first: start.1,
+                            last: end.1,
+                            append: false,
+                            to_eol,
+                            width: end.0 - start.0 + 1,
+                        });
+                    }
+                }
+                b'>' | b'<' => {
+                    shift_lines(context, cursor, data, start.1, end.1, k == b'>', count);
+                }
+                b'~' | b'u' | b'U' => {
+                    for (l, r) in ranges.iter().rev() {
+                        let mapped = change_case(&data.buffer[*l..*r], k);
+                        data.replace_slice(*l..*r, &mapped);
+                    }
+                    context.borrow_mut().dirty = 1;
+                    cursor.borrow_mut().set_y(start.1);
+                    cursor.borrow_mut().set_x(start.0);
+                }
+                b'J' => {
+                    join_lines(context, cursor, data, start.1, end.1 - start.1 + 1, true);
+                }
+                _ => (),
+            }
+            Some(EditorHealth::Healthy)
+        }
+        // Ctrl-A and Ctrl-X on a selection change the first number of every row that starts
+        // inside it. With g (progressive) the n-th number found changes by n times count,
+        // which turns a column of zeros into 1, 2, 3...
+        pub(crate) fn visual_increment(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            k: u8,
+            progressive: bool,
+        ) -> Option<EditorHealth> {
+            let (start, end) = visual_bounds(&context.borrow(), &cursor.borrow());
+            let count = context.borrow().count() as i64;
+            let mut columns = Vec::new();
+            for y in start.1..=end.1.min(data.new_lines.len().saturating_sub(1)) {
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, &data.new_lines);
+                let cols = row_columns(
+                    &context.borrow(),
+                    &cursor.borrow(),
+                    y,
+                    line_end - line_begin,
+                );
+                columns.push((y, cols));
+            }
+            leave_visual(context, cursor, data);
+            let sign = if k == 0x18 { -1 } else { 1 };
+            let mut step = 0;
+            for (y, cols) in columns {
+                let (from, to) = match cols {
+                    Some(cols) => cols,
+                    None => continue,
+                };
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, &data.new_lines);
+                let line = &data.buffer[line_begin..line_end];
+                let span = match find_number(line, from) {
+                    Some(span) if span.start < to => span,
+                    _ => continue,
+                };
+                step = if progressive { step + 1 } else { 1 };
+                let number = add_to_number(line, span, sign * count * step);
+                data.replace_slice(line_begin + span.start..line_begin + span.end, &number);
+                context.borrow_mut().dirty = 1;
+            }
+            cursor.borrow_mut().set_y(start.1);
+            cursor
+                .borrow_mut()
+                .set_x(match context.borrow().last_visual {
+                    Some((EditorModes::VisualLine, _, _)) => 0,
+                    _ => start.0,
+                });
+            Some(EditorHealth::Healthy)
+        }
+        // I or A on a block: typing happens on the top row, finish_block_insert copies it
+        // to the other rows when insert mode ends
+        pub(crate) fn start_block_insert(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            k: u8,
+        ) -> Option<EditorHealth> {
+            let (start, end) = visual_bounds(&context.borrow(), &cursor.borrow());
+            let to_eol = context.borrow().visual_to_eol;
+            leave_visual(context, cursor, data);
+            let append = k == b'A';
+            let column = if append { end.0 + 1 } else { start.0 };
+            let (line_begin, line_end) = cursor
+                .borrow()
+                .calculate_row_of_insert_indices(start.1, &data.new_lines);
+            let len = line_end - line_begin;
+            let x = match (append, to_eol) {
+                (true, true) => len,
+                (true, false) => {
+                    if len < column {
+                        data.insert_slice(line_end, " ".repeat(column - len).as_bytes());
+                    }
+                    column
                 }
+                (false, _) => column.min(len),
+            };
+            cursor.borrow_mut().set_y(start.1);
+            cursor.borrow_mut().set_x(x);
+            let mut c = context.borrow_mut();
+            c.block_insert = Some(BlockInsert {
+                column,
+                first: start.1,
+                last: end.1,
+                append,
+                to_eol,
+                width: 0,
+            });
+            c.insert_change = Some(Operator::new(
+                (k as char).to_string(),
+                operations::insert::replay_insert,
+            ));
+            c.mode = EditorModes::Insert;
+            Some(EditorHealth::Healthy)
+        }
+        // Types `keys` on the rows below the top one. Like vim, rows too short to reach
+        // the column are skipped by I and padded by A, and text with a line break only
+        // stays on the top row.
+        pub(crate) fn finish_block_insert(
+            context: &Rc<RefCell<EditorContext>>,
+            cursor: &Rc<RefCell<Cursor>>,
+            data: &mut AppendBuffer,
+            block: BlockInsert,
+            keys: &[u8],
+        ) {
+            if keys.is_empty() || keys.contains(&13) {
+                return;
+            }
+            for y in block.first + 1..=block.last.min(data.new_lines.len().saturating_sub(1)) {
+                let (line_begin, line_end) = cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(y, &data.new_lines);
+                let len = line_end - line_begin;
+                let x = match (block.append, block.to_eol) {
+                    (true, true) => len,
+                    (true, false) => {
+                        if len < block.column {
+                            data.insert_slice(line_end, " ".repeat(block.column - len).as_bytes());
+                        }
+                        block.column
+                    }
+                    (false, _) if len < block.column => continue,
+                    (false, _) => block.column,
+                };
+                cursor.borrow_mut().set_y(y);
+                cursor.borrow_mut().set_x(x);
+                operations::insert::type_keys(context, cursor, data, keys);
+            }
+            cursor.borrow_mut().set_y(block.first);
+            cursor.borrow_mut().set_x(block.column);
+            context.borrow_mut().dirty = 1;
+        }
+        // `.` after a block insert types the text on `count` rows from the cursor down
+        pub(crate) fn replay_block_insert(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            let (x, y) = (editor.cursor.borrow().absx(), editor.cursor.borrow().absy());
+            let append = op.signature.ends_with('A');
+            let block = BlockInsert {
+                column: if append { x + 1 } else { x },
+                first: y,
+                last: y + count - 1,
+                append,
+                to_eol: false,
+                width: 0,
+            };
+            editor.cursor.borrow_mut().set_x(block.column);
+            operations::insert::type_keys(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                &op.inserted,
+            );
+            finish_block_insert(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                block,
+                &op.inserted,
+            );
+            Some(EditorHealth::Healthy)
+        }
+        // `.` after changing a block takes the same columns out of `count` rows from the
+        // cursor down, to the end of them for $, then types the text like a block insert
+        pub(crate) fn replay_block_change(
+            editor: &mut Editor,
+            op: &Operator,
+            count: usize,
+        ) -> Option<EditorHealth> {
+            let (x, y) = (editor.cursor.borrow().absx(), editor.cursor.borrow().absy());
+            let last = (y + count - 1).min(editor.data.new_lines.len().saturating_sub(1));
+            let to_eol = op.motion == "$";
+            let width = op.motion.parse().unwrap_or(0);
+            let mut ranges = Vec::new();
+            for row in y..=last {
+                let (line_begin, line_end) = editor
+                    .cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(row, &editor.data.new_lines);
+                let len = line_end - line_begin;
+                let end = if to_eol { len } else { (x + width).min(len) };
+                ranges.push((line_begin + x.min(len), line_begin + end));
             }
+            delete_ranges(
+                &editor.context,
+                &editor.terminal,
+                &mut editor.data,
+                &ranges,
+                EditorModes::VisualBlock,
+            );
+            let block = BlockInsert {
+                column: x,
+                first: y,
+                last,
+                append: false,
+                to_eol,
+                width,
+            };
+            editor.cursor.borrow_mut().set_y(y);
+            editor.cursor.borrow_mut().set_x(x);
+            operations::insert::type_keys(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                &op.inserted,
+            );
+            finish_block_insert(
+                &editor.context,
+                &editor.cursor,
+                &mut editor.data,
+                block,
+                &op.inserted,
+            );
             Some(EditorHealth::Healthy)
         }
+        pub(crate) fn delete_ranges(
+            context: &Rc<RefCell<EditorContext>>,
+            terminal: &Rc<RefCell<Terminal>>,
+            data: &mut AppendBuffer,
+            ranges: &[(usize, usize)],
+            mode: EditorModes,
+        ) {
+            let text = selected_text(data, ranges);
+            for (l, r) in ranges.iter().rev() {
+                data.remove_slice(*l..*r);
+            }
+            data.update_buffers();
+            store_register(
+                context,
+                terminal,
+                RegisterOp::Delete,
+                register_kind(mode),
+                text,
+            );
+            context.borrow_mut().dirty = 1;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::editor::testing::{editor, type_script};
+
+    #[test]
+    fn dot_repeats_a_delete() {
+        let mut editor = editor(b"abcdef\n");
+        type_script(&mut editor, b"dl.");
+        assert_eq!(editor.data.buffer, b"cdef\n");
+    }
+
+    #[test]
+    fn dot_takes_a_new_count() {
+        let mut editor = editor(b"abcdefghijkl\n");
+        type_script(&mut editor, b"2dl3.");
+        assert_eq!(editor.data.buffer, b"fghijkl\n");
+        // The new count sticks for the next .
+        type_script(&mut editor, b".");
+        assert_eq!(editor.data.buffer, b"ijkl\n");
+    }
+
+    #[test]
+    fn dot_replays_an_insert() {
+        let mut editor = editor(b"ab\n");
+        type_script(&mut editor, b"ix-\x1B.");
+        assert_eq!(editor.data.buffer, b"x-x-ab\n");
+    }
+
+    #[test]
+    fn dot_replays_an_append_at_the_end() {
+        let mut editor = editor(b"ab\n");
+        type_script(&mut editor, b"A!\x1B.");
+        assert_eq!(editor.data.buffer, b"ab!!\n");
+    }
+
+    #[test]
+    fn named_register_yank_and_put() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"\"ayyj\"ap");
+        assert_eq!(editor.data.buffer, b"one\ntwo\none\n");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+    }
+
+    #[test]
+    fn put_before_and_with_a_count() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"jY2P");
+        assert_eq!(editor.data.buffer, b"one\ntwo\ntwo\ntwo\n");
+        assert_eq!(editor.cursor.borrow().absy(), 1);
+    }
+
+    #[test]
+    fn charwise_put_goes_after_the_cursor() {
+        let mut editor = editor(b"abc\n");
+        type_script(&mut editor, b"dlp");
+        assert_eq!(editor.data.buffer, b"bac\n");
+        assert_eq!(editor.cursor.borrow().absx(), 1);
+    }
+
+    #[test]
+    fn gp_leaves_the_cursor_after_the_text() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"yygp");
+        assert_eq!(editor.data.buffer, b"one\none\ntwo\n");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+    }
+    use crate::editor::{EditorModes, RegisterKind};
+
+    #[test]
+    fn charwise_visual_delete() {
+        let mut editor = editor(b"abcdef\n");
+        type_script(&mut editor, b"lvlld");
+        assert_eq!(editor.data.buffer, b"aef\n");
+        assert_eq!(editor.context.borrow().mode, EditorModes::Normal);
+    }
+
+    #[test]
+    fn linewise_visual_yank_is_linewise() {
+        let mut editor = editor(b"one\ntwo\nthree\n");
+        type_script(&mut editor, b"Vjy");
+        let register = editor.context.borrow().registers.get(None, "").unwrap();
+        assert_eq!(register.kind, RegisterKind::Linewise);
+        assert_eq!(register.text, b"one\ntwo\n");
+        assert_eq!(editor.data.buffer, b"one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn blockwise_visual_delete_takes_the_columns() {
+        let mut editor = editor(b"abcd\nefgh\nijkl\n");
+        type_script(&mut editor, b"l\x16jld");
+        assert_eq!(editor.data.buffer, b"ad\neh\nijkl\n");
+        let register = editor.context.borrow().registers.get(None, "").unwrap();
+        assert_eq!(register.kind, RegisterKind::Blockwise);
+        assert_eq!(register.text, b"bc\nfg");
+    }
+
+    #[test]
+    fn gv_reselects_and_o_swaps_ends() {
+        let mut editor = editor(b"abcdef\n");
+        type_script(&mut editor, b"vll\x1Bgvohd");
+        assert_eq!(editor.data.buffer, b"def\n");
+    }
+
+    #[test]
+    fn block_insert_goes_on_every_row() {
+        let mut editor = editor(b"abc\ndef\nghi\n");
+        type_script(&mut editor, b"l\x16jIX\x1B");
+        assert_eq!(editor.data.buffer, b"aXbc\ndXef\nghi\n");
+    }
+
+    #[test]
+    fn block_append_after_dollar_goes_at_every_line_end() {
+        let mut editor = editor(b"ab\nlonger\n");
+        type_script(&mut editor, b"\x16j$A;\x1B");
+        assert_eq!(editor.data.buffer, b"ab;\nlonger;\n");
+    }
+
+    #[test]
+    fn block_change_repeats_with_dot() {
+        let mut editor = editor(b"abcd\nefgh\nijkl\nmnop\n");
+        type_script(&mut editor, b"\x16jlcZ\x1B");
+        assert_eq!(editor.data.buffer, b"Zcd\nZgh\nijkl\nmnop\n");
+        type_script(&mut editor, b"jj.");
+        assert_eq!(editor.data.buffer, b"Zcd\nZgh\nZkl\nZop\n");
+    }
+
+    #[test]
+    fn replace_mode_overtypes_and_backspace_restores() {
+        let mut editor = editor(b"abcd\n");
+        type_script(&mut editor, b"RXYZ\x7F\x1B");
+        assert_eq!(editor.data.buffer, b"XYcd\n");
+        assert_eq!(editor.context.borrow().mode, EditorModes::Normal);
+    }
+
+    #[test]
+    fn replace_mode_appends_past_the_line_end() {
+        let mut editor = editor(b"ab\nc\n");
+        type_script(&mut editor, b"lRxyz\x1B");
+        assert_eq!(editor.data.buffer, b"axyz\nc\n");
+    }
+
+    #[test]
+    fn r_with_a_count_replaces_that_many() {
+        let mut editor = editor(b"abcd\n");
+        type_script(&mut editor, b"3rx");
+        assert_eq!(editor.data.buffer, b"xxxd\n");
+        // Too few characters left and nothing happens
+        type_script(&mut editor, b"9ry");
+        assert_eq!(editor.data.buffer, b"xxxd\n");
+    }
+
+    #[test]
+    fn o_and_cap_o_open_lines_with_the_indent() {
+        let mut editor = editor(b"  one\n");
+        type_script(&mut editor, b"otwo\x1BOmid\x1B");
+        assert_eq!(editor.data.buffer, b"  one\n  mid\n  two\n");
+    }
+
+    #[test]
+    fn j_joins_with_a_space_and_gj_without() {
+        let mut editor = editor(b"one\n   two\nthree\nfour\n");
+        type_script(&mut editor, b"J");
+        assert_eq!(editor.data.buffer, b"one two\nthree\nfour\n");
+        type_script(&mut editor, b"jgJ");
+        assert_eq!(editor.data.buffer, b"one two\nthreefour\n");
+    }
+
+    #[test]
+    fn cap_c_and_s_change_to_the_end_and_the_line() {
+        let mut editor = editor(b"abcd\n  efgh\n");
+        type_script(&mut editor, b"llCX\x1BjSY\x1B");
+        assert_eq!(editor.data.buffer, b"abX\n  Y\n");
+    }
+
+    #[test]
+    fn x_cap_x_and_cap_d_delete() {
+        let mut editor = editor(b"abcdef\n");
+        type_script(&mut editor, b"llxX");
+        assert_eq!(editor.data.buffer, b"adef\n");
+        type_script(&mut editor, b"D");
+        assert_eq!(editor.data.buffer, b"a\n");
+        let register = editor.context.borrow().registers.get(None, "").unwrap();
+        assert_eq!(register.text, b"def");
+    }
+
+    #[test]
+    fn s_substitutes_count_characters() {
+        let mut editor = editor(b"abcdef\n");
+        type_script(&mut editor, b"3sX\x1B");
+        assert_eq!(editor.data.buffer, b"Xdef\n");
+    }
+
+    #[test]
+    fn dot_replays_a_change_with_a_count() {
+        let mut editor = editor(b"abcdefgh\n");
+        type_script(&mut editor, b"clX\x1Bl3.");
+        assert_eq!(editor.data.buffer, b"XbXfgh\n");
+    }
+
+    #[test]
+    fn tilde_and_case_operators() {
+        let mut editor = editor(b"abc\nMixed Case\n");
+        type_script(&mut editor, b"~~");
+        assert_eq!(editor.data.buffer, b"ABc\nMixed Case\n");
+        type_script(&mut editor, b"jgUU");
+        assert_eq!(editor.data.buffer, b"ABc\nMIXED CASE\n");
+        type_script(&mut editor, b"guu");
+        assert_eq!(editor.data.buffer, b"ABc\nmixed case\n");
+    }
+
+    #[test]
+    fn ctrl_a_and_ctrl_x_on_decimals() {
+        let mut editor = editor(b"x 9 y\n");
+        type_script(&mut editor, b"\x01");
+        assert_eq!(editor.data.buffer, b"x 10 y\n");
+        type_script(&mut editor, b"15\x18");
+        assert_eq!(editor.data.buffer, b"x -5 y\n");
+        type_script(&mut editor, b".");
+        assert_eq!(editor.data.buffer, b"x -20 y\n");
+    }
+
+    #[test]
+    fn hex_keeps_its_width_and_ignores_the_minus() {
+        let mut minus = editor(b"-0x0f\n");
+        type_script(&mut minus, b"\x01");
+        assert_eq!(minus.data.buffer, b"-0x10\n");
+        let mut upper = editor(b"0xFF\n");
+        type_script(&mut upper, b"\x01");
+        assert_eq!(upper.data.buffer, b"0x100\n");
+    }
+
+    #[test]
+    fn leading_zero_is_octal_and_0b_binary() {
+        let mut editor = editor(b"007 0b11\n");
+        type_script(&mut editor, b"\x01");
+        assert_eq!(editor.data.buffer, b"010 0b11\n");
+        type_script(&mut editor, b"l\x01");
+        assert_eq!(editor.data.buffer, b"010 0b100\n");
+    }
+
+    #[test]
+    fn visual_g_ctrl_a_counts_up_per_line() {
+        let mut editor = editor(b"0\n0\n0\n");
+        type_script(&mut editor, b"Vjjg\x01");
+        assert_eq!(editor.data.buffer, b"1\n2\n3\n");
+    }
+
+    #[test]
+    fn ctrl_w_removes_the_word_before_the_cursor() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b"ifoo bar.baz  \x17\x17\x1B");
+        assert_eq!(editor.data.buffer, b"foo bar\n");
+        type_script(&mut editor, b"A\x17\x1B");
+        assert_eq!(editor.data.buffer, b"foo \n");
+    }
+
+    #[test]
+    fn ctrl_u_keeps_the_indent() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b"i    text\x15x\x1B");
+        assert_eq!(editor.data.buffer, b"    x\n");
+        // In the indent it takes the indent too
+        type_script(&mut editor, b"A\x15\x15\x1B");
+        assert_eq!(editor.data.buffer, b"\n");
+    }
+
+    #[test]
+    fn ctrl_h_is_backspace_and_joins_lines() {
+        let mut editor = editor(b"ab\ncd\n");
+        type_script(&mut editor, b"ji\x08\x08\x1B");
+        assert_eq!(editor.data.buffer, b"acd\n");
+    }
+
+    #[test]
+    fn ctrl_t_and_ctrl_d_shift_the_line() {
+        let mut editor = editor(b"text\n");
+        type_script(&mut editor, b":set sw=2\r");
+        assert_eq!(editor.context.borrow().status_message, "");
+        type_script(&mut editor, b"A\x14\x14\x04!\x1B");
+        assert_eq!(editor.data.buffer, b"  text!\n");
+    }
+
+    #[test]
+    fn ctrl_r_inserts_a_register_and_ctrl_v_quotes() {
+        let mut editor = editor(b"word\n");
+        type_script(&mut editor, b"\"ayyA \x12a\x16\x01\x1B");
+        assert_eq!(editor.data.buffer, b"word word\n\x01\n");
+    }
+
+    #[test]
+    fn ctrl_o_runs_one_normal_command() {
+        let mut editor = editor(b"abc\n");
+        type_script(&mut editor, b"iX\x0Fxy\x1B");
+        assert_eq!(editor.data.buffer, b"Xybc\n");
+        assert_eq!(editor.context.borrow().mode, EditorModes::Normal);
     }
 }
@@ -0,0 +1,634 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::editor::Options;
+
+// What yim asks of the file system about the files behind buffers, and how it writes
+// them. A write either goes to a new file renamed over the old one, so a crash leaves
+// one of the two whole, or over the old file itself when its links or owner have to
+// stay, guarded by a backup copy ('backupcopy', 'writebackup').
+
+// Whether this process may write `path`, as access(2) sees it
+pub(crate) fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+// What a file looked like when it was read or written, another program writing it
+// changes at least one of these
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    mtime: (i64, i64),
+    size: u64,
+    inode: u64,
+}
+
+impl FileStamp {
+    // None when there is no such file
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            mtime: (metadata.mtime(), metadata.mtime_nsec()),
+            size: metadata.size(),
+            inode: metadata.ino(),
+        })
+    }
+}
+
+// The encoding a file is in, its 'fileencoding', and whether it starts with a byte order
+// mark ('bomb'). Without a mark it is UTF-16 when NUL bytes sit in every other byte, then
+// UTF-8 when that reads, and when there are UTF-8 characters among bytes that aren't,
+// which decode then refuses. Latin-1 is left for text without a single UTF-8 character,
+// it would turn every one of them into two.
+pub(crate) fn detect_encoding(bytes: &[u8]) -> (&'static str, bool) {
+    let utf8_characters = || {
+        String::from_utf8_lossy(bytes)
+            .chars()
+            .any(|c| !c.is_ascii() && c != char::REPLACEMENT_CHARACTER)
+    };
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => ("utf-8", true),
+        [0xFF, 0xFE, ..] => ("utf-16le", true),
+        [0xFE, 0xFF, ..] => ("utf-16be", true),
+        _ => match utf16_without_mark(bytes) {
+            Some(encoding) => (encoding, false),
+            None if std::str::from_utf8(bytes).is_ok() || utf8_characters() => ("utf-8", false),
+            None => ("latin1", false),
+        },
+    }
+}
+
+// Text files have no NUL bytes, except the high bytes of UTF-16 characters below U+0100.
+// Those all fall on odd offsets in little endian and even ones in big endian, taken as
+// UTF-16 when they are at least half the characters and the whole file decodes.
+fn utf16_without_mark(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let nuls = |first: usize| {
+        bytes
+            .iter()
+            .skip(first)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let half = bytes.len() / 2;
+    let encoding = match (nuls(0), nuls(1)) {
+        (0, odd) if odd * 2 >= half => "utf-16le",
+        (even, 0) if even * 2 >= half => "utf-16be",
+        _ => return None,
+    };
+    decode(bytes, encoding, false).map(|_| encoding)
+}
+
+fn byte_order_mark(encoding: &str) -> &'static [u8] {
+    match encoding {
+        "utf-8" => &[0xEF, 0xBB, 0xBF],
+        "utf-16le" => &[0xFF, 0xFE],
+        "utf-16be" => &[0xFE, 0xFF],
+        _ => &[],
+    }
+}
+
+// The buffer holds UTF-8, the text of a file in another encoding is converted on reading
+// it and back on writing it. None when the bytes aren't valid in `encoding`.
+pub(crate) fn decode(bytes: &[u8], encoding: &str, bom: bool) -> Option<Vec<u8>> {
+    let bytes = match bom {
+        true => bytes.strip_prefix(byte_order_mark(encoding))?,
+        false => bytes,
+    };
+    let text = match encoding {
+        "utf-8" => std::str::from_utf8(bytes).ok()?.to_string(),
+        "latin1" => bytes.iter().map(|&b| b as char).collect(),
+        "utf-16le" | "utf-16be" if bytes.len() % 2 == 0 => {
+            let units = bytes.chunks(2).map(|pair| match encoding {
+                "utf-16le" => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            char::decode_utf16(units).collect::<Result<_, _>>().ok()?
+        }
+        _ => return None,
+    };
+    Some(text.into_bytes())
+}
+
+// None when the text has characters `encoding` can't hold. UTF-8 is written as it is,
+// bytes that weren't valid to begin with included.
+pub(crate) fn encode(text: &[u8], encoding: &str, bom: bool) -> Option<Vec<u8>> {
+    let mut bytes = match bom {
+        true => byte_order_mark(encoding).to_vec(),
+        false => Vec::new(),
+    };
+    match encoding {
+        "utf-8" => bytes.extend_from_slice(text),
+        "latin1" => {
+            for c in std::str::from_utf8(text).ok()?.chars() {
+                bytes.push(u8::try_from(c).ok()?);
+            }
+        }
+        "utf-16le" | "utf-16be" => {
+            for unit in std::str::from_utf8(text).ok()?.encode_utf16() {
+                bytes.extend(match encoding {
+                    "utf-16le" => unit.to_le_bytes(),
+                    _ => unit.to_be_bytes(),
+                });
+            }
+        }
+        _ => return None,
+    }
+    Some(bytes)
+}
+
+// The line ends a file uses, its 'fileformat': "dos" when every line ends in CR LF,
+// "mac" for CRs without a single LF and "unix" for anything else
+pub(crate) fn detect_format(bytes: &[u8]) -> &'static str {
+    let lf = bytes.iter().filter(|&&b| b == b'\n').count();
+    let crlf = bytes.windows(2).filter(|w| w == b"\r\n").count();
+    match (lf, crlf) {
+        (0, _) if bytes.contains(&b'\r') => "mac",
+        (lf, crlf) if lf > 0 && lf == crlf => "dos",
+        _ => "unix",
+    }
+}
+
+// The buffer only has LFs, the file's line ends go on reading it and come back on
+// writing it
+pub(crate) fn from_format(bytes: &[u8], format: &str) -> Vec<u8> {
+    match format {
+        "dos" => {
+            let mut text = Vec::with_capacity(bytes.len());
+            let mut bytes = bytes.iter().peekable();
+            while let Some(&b) = bytes.next() {
+                if b != b'\r' || bytes.peek() != Some(&&b'\n') {
+                    text.push(b);
+                }
+            }
+            text
+        }
+        "mac" => bytes
+            .iter()
+            .map(|&b| if b == b'\r' { b'\n' } else { b })
+            .collect(),
+        _ => bytes.to_vec(),
+    }
+}
+
+pub(crate) fn to_format(text: &[u8], format: &str) -> Vec<u8> {
+    match format {
+        "dos" => {
+            let mut bytes = Vec::with_capacity(text.len());
+            for &b in text {
+                if b == b'\n' {
+                    bytes.push(b'\r');
+                }
+                bytes.push(b);
+            }
+            bytes
+        }
+        "mac" => text
+            .iter()
+            .map(|&b| if b == b'\n' { b'\r' } else { b })
+            .collect(),
+        _ => text.to_vec(),
+    }
+}
+
+// `path` with 'backupext' added to its name
+fn backup_path(path: &Path, options: &Options) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(options.string("backupext"));
+    PathBuf::from(name)
+}
+
+// The directory `path` is in
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn make_backup(path: &Path, options: &Options) -> io::Result<PathBuf> {
+    let backup = backup_path(path, options);
+    fs::copy(path, &backup).map_err(|err| {
+        io::Error::new(
+            err.kind(),
+            format!("E510: Can't make backup file ({})", err),
+        )
+    })?;
+    Ok(backup)
+}
+
+// Writes `text` to `path`, errors come back with the message to show for them
+pub(crate) fn write_file(path: &Path, text: &[u8], options: &Options) -> io::Result<()> {
+    let link = fs::symlink_metadata(path).ok();
+    let metadata = fs::metadata(path).ok();
+    let euid = unsafe { libc::geteuid() };
+    let dir_writable = is_writable(parent_dir(path));
+    let auto = options.string("backupcopy") == "auto";
+    let in_place = match options.string("backupcopy") {
+        "yes" => true,
+        "no" => false,
+        // A rename would turn a link into a file of its own, leave the file owned by
+        // us when we can't give it back, and needs a directory to make the new file in
+        _ => {
+            !dir_writable
+                || link.as_ref().is_some_and(|l| l.file_type().is_symlink())
+                || metadata
+                    .as_ref()
+                    .is_some_and(|m| m.nlink() > 1 || (m.uid() != euid && euid != 0))
+        }
+    };
+    let mut backup = match metadata.is_some() && options.flag("backup") {
+        true => Some(make_backup(path, options)?),
+        false => None,
+    };
+    if !in_place {
+        match write_renamed(path, text, metadata.as_ref(), auto) {
+            // The new file couldn't be made, or not with the old one's owner and group,
+            // and the old one is still there to be written over
+            Err(err)
+                if auto
+                    && matches!(
+                        err.kind(),
+                        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+                    ) => {}
+            written => return finish_write(written, backup, options),
+        }
+    }
+    // Renaming leaves the old file alone until the new one is complete, only writing
+    // over it needs a copy to fall back on, when its directory can take one
+    if backup.is_none() && metadata.is_some() && options.flag("writebackup") && dir_writable {
+        backup = Some(make_backup(path, options)?);
+    }
+    finish_write(write_in_place(path, text), backup, options)
+}
+
+// The backup made only for the write goes once it worked, a failed write says where
+// the old text is
+fn finish_write(
+    written: io::Result<()>,
+    backup: Option<PathBuf>,
+    options: &Options,
+) -> io::Result<()> {
+    match (written, backup) {
+        (Ok(()), Some(backup)) if !options.flag("backup") => {
+            let _ = fs::remove_file(backup);
+            Ok(())
+        }
+        (Err(err), Some(backup)) => Err(io::Error::new(
+            err.kind(),
+            format!("{}, the old text is in {}", err, backup.display()),
+        )),
+        (written, _) => written,
+    }
+}
+
+fn open_error(err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("E212: Can't open file for writing ({})", err),
+    )
+}
+
+fn write_error(err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("E514: Write error ({})", err))
+}
+
+fn write_in_place(path: &Path, text: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .map_err(open_error)?;
+    file.write_all(text).map_err(write_error)?;
+    file.sync_all().map_err(write_error)
+}
+
+// The new text goes to a file next to `path` first, taking its permissions and owner.
+// With `keep_owner` a file that can't get the owner and group of `path` isn't used.
+fn write_renamed(
+    path: &Path,
+    text: &[u8],
+    metadata: Option<&fs::Metadata>,
+    keep_owner: bool,
+) -> io::Result<()> {
+    let dir = parent_dir(path);
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".yim{}", std::process::id()));
+    let temp = dir.join(name);
+    // Left by a yim that crashed with the pid this one has now
+    let _ = fs::remove_file(&temp);
+    let mode = metadata.map_or(0o666, |m| m.mode() & 0o7777);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temp)
+        .map_err(open_error)?;
+    let written = (|| {
+        file.write_all(text)?;
+        if let Some(metadata) = metadata {
+            // The umask went into the mode above, and only root gives files away
+            file.set_permissions(metadata.permissions())?;
+            let owned = unsafe { libc::fchown(file.as_raw_fd(), metadata.uid(), metadata.gid()) };
+            if owned != 0 && keep_owner {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(write_error(err));
+    }
+    // The rename only lasts once the directory is on disk too
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::temp_dir;
+    use crate::editor::SetScope;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn options(args: &[&str]) -> Options {
+        let mut options = Options::default();
+        for arg in args {
+            options.set(arg, SetScope::Both).unwrap();
+        }
+        options
+    }
+
+    // Reads `bytes` the way open does and writes them back the way save does
+    fn round_trip_format(bytes: &[u8]) -> (&'static str, Vec<u8>, Vec<u8>) {
+        let format = detect_format(bytes);
+        let text = from_format(bytes, format);
+        let written = to_format(&text, format);
+        (format, text, written)
+    }
+
+    #[test]
+    fn crlf_is_dos() {
+        let (format, text, written) = round_trip_format(b"one\r\ntwo\r\n");
+        assert_eq!(format, "dos");
+        assert_eq!(text, b"one\ntwo\n");
+        assert_eq!(written, b"one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn cr_only_is_mac() {
+        let (format, text, written) = round_trip_format(b"one\rtwo\r");
+        assert_eq!(format, "mac");
+        assert_eq!(text, b"one\ntwo\n");
+        assert_eq!(written, b"one\rtwo\r");
+    }
+
+    #[test]
+    fn mixed_line_ends_are_kept() {
+        let (format, text, written) = round_trip_format(b"one\r\ntwo\nthree\r");
+        assert_eq!(format, "unix");
+        assert_eq!(text, b"one\r\ntwo\nthree\r");
+        assert_eq!(written, b"one\r\ntwo\nthree\r");
+    }
+
+    #[test]
+    fn cr_before_crlf_stays_in_the_line() {
+        let (format, text, written) = round_trip_format(b"one\r\r\ntwo\r\n");
+        assert_eq!(format, "dos");
+        assert_eq!(text, b"one\r\ntwo\n");
+        assert_eq!(written, b"one\r\r\ntwo\r\n");
+    }
+
+    // Reads `bytes` the way open does and writes them back the way save does
+    fn round_trip_encoding(bytes: &[u8]) -> (&'static str, bool, Vec<u8>, Vec<u8>) {
+        let (encoding, bom) = detect_encoding(bytes);
+        let text = decode(bytes, encoding, bom).expect("bytes in their own encoding");
+        let written = encode(&text, encoding, bom).expect("text from the same encoding");
+        (encoding, bom, text, written)
+    }
+
+    #[test]
+    fn utf16le_with_bom() {
+        let bytes = [0xFF, 0xFE, b'h', 0, 0xE9, 0, b'\n', 0];
+        let (encoding, bom, text, written) = round_trip_encoding(&bytes);
+        assert_eq!((encoding, bom), ("utf-16le", true));
+        assert_eq!(text, "hé\n".as_bytes());
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn utf16be_with_bom() {
+        let bytes = [0xFE, 0xFF, 0, b'h', 0xD8, 0x3D, 0xDE, 0x00, 0, b'\n'];
+        let (encoding, bom, text, written) = round_trip_encoding(&bytes);
+        assert_eq!((encoding, bom), ("utf-16be", true));
+        assert_eq!(text, "h😀\n".as_bytes());
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn utf16_without_bom_is_told_by_its_nul_bytes() {
+        let bytes = [b'h', 0, 0xE9, 0, b'\n', 0];
+        let (encoding, bom, text, written) = round_trip_encoding(&bytes);
+        assert_eq!((encoding, bom), ("utf-16le", false));
+        assert_eq!(text, "hé\n".as_bytes());
+        assert_eq!(written, bytes);
+        let bytes = [0, b'h', 0x20, 0xAC, 0, b'\n'];
+        let (encoding, bom, text, written) = round_trip_encoding(&bytes);
+        assert_eq!((encoding, bom), ("utf-16be", false));
+        assert_eq!(text, "h€\n".as_bytes());
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn nul_bytes_out_of_step_are_not_utf16() {
+        // NULs on both sides, and an odd length
+        assert_eq!(detect_encoding(b"a\0\0b"), ("utf-8", false));
+        assert_eq!(detect_encoding(b"a\0b"), ("utf-8", false));
+        // Too few of them to be the high bytes of the text
+        assert_eq!(detect_encoding(b"abcdef\0g"), ("utf-8", false));
+        assert_eq!(detect_encoding(b"abcdefg\0"), ("utf-8", false));
+    }
+
+    #[test]
+    fn invalid_utf8_reads_as_latin1() {
+        let bytes = b"caf\xE9 \xFF\n";
+        let (encoding, bom, text, written) = round_trip_encoding(bytes);
+        assert_eq!((encoding, bom), ("latin1", false));
+        assert_eq!(text, "café ÿ\n".as_bytes());
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn stray_byte_in_utf8_is_not_latin1() {
+        let bytes = b"na\xC3\xAFve \xFF\n".to_vec();
+        assert_eq!(detect_encoding(&bytes), ("utf-8", false));
+        assert_eq!(decode(&bytes, "utf-8", false), None);
+        assert_eq!(encode(&bytes, "utf-8", false), Some(bytes));
+    }
+
+    #[test]
+    fn latin1_cannot_hold_other_characters() {
+        assert_eq!(encode("€".as_bytes(), "latin1", false), None);
+    }
+
+    #[test]
+    fn write_creates_a_missing_file() {
+        let path = temp_dir("write-new").join("new.txt");
+        write_file(&path, b"text\n", &options(&[])).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"text\n");
+    }
+
+    #[test]
+    fn renamed_write_keeps_permissions_and_leaves_no_backup() {
+        let dir = temp_dir("write-renamed");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+        write_file(&path, b"new\n", &options(&["backupcopy=no"])).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(metadata.mode() & 0o777, 0o640);
+        assert_ne!(metadata.ino(), inode);
+        // Only the file itself is left, no temp file and no backup
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn in_place_write_keeps_the_inode() {
+        let dir = temp_dir("write-in-place");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old text\n").unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+        write_file(&path, b"new\n", &options(&["backupcopy=yes"])).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+        // 'writebackup' made a copy for the write and took it away again
+        assert!(!dir.join("file.txt~").exists());
+    }
+
+    #[test]
+    fn backup_keeps_the_old_text() {
+        let dir = temp_dir("write-backup");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        write_file(&path, b"new\n", &options(&["backup", "bex=.bak"])).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(fs::read(dir.join("file.txt.bak")).unwrap(), b"old\n");
+    }
+
+    #[test]
+    fn failed_in_place_write_names_the_backup() {
+        let dir = temp_dir("write-failed");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o444)).unwrap();
+        // Root writes anyway
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let result = write_file(&path, b"new\n", &options(&["backupcopy=yes"]));
+        let err = result.unwrap_err().to_string();
+        assert!(err.starts_with("E212"), "{}", err);
+        assert!(err.ends_with("file.txt~"), "{}", err);
+        assert_eq!(fs::read(dir.join("file.txt~")).unwrap(), b"old\n");
+    }
+
+    #[test]
+    fn in_place_without_writebackup_makes_no_copy() {
+        let dir = temp_dir("write-nowb");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        write_file(
+            &path,
+            b"new\n",
+            &options(&["backupcopy=yes", "nowritebackup"]),
+        )
+        .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn stale_temp_file_is_replaced() {
+        let dir = temp_dir("write-stale");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        let stale = dir.join(format!(".file.txt.yim{}", std::process::id()));
+        fs::write(&stale, b"crashed\n").unwrap();
+        write_file(&path, b"new\n", &options(&["backupcopy=no"])).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert!(!stale.exists());
+    }
+
+    #[test]
+    fn file_in_a_read_only_directory_is_written_in_place() {
+        let dir = temp_dir("write-ro-dir");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        let inode = fs::metadata(&path).unwrap().ino();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // Root writes the directory anyway
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let written = write_file(&path, b"new\n", &options(&[]));
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        written.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn new_file_that_cannot_be_made_leaves_the_old_one() {
+        let dir = temp_dir("write-no-temp");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let metadata = fs::metadata(&path).unwrap();
+        let written = write_renamed(&path, b"new\n", Some(&metadata), true);
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        // What write_file takes for writing in place instead
+        assert_eq!(written.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read(&path).unwrap(), b"old\n");
+    }
+
+    #[test]
+    fn new_file_without_the_group_is_not_used() {
+        let dir = temp_dir("write-group");
+        let path = dir.join("file.txt");
+        fs::write(&path, b"old\n").unwrap();
+        // Only root can give the new file the owner and group of /
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let root = fs::metadata("/").unwrap();
+        let written = write_renamed(&path, b"new\n", Some(&root), true);
+        assert_eq!(written.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(fs::read(&path).unwrap(), b"old\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        // backupcopy=no renames anyway
+        write_renamed(&path, b"new\n", Some(&root), false).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new\n");
+    }
+}
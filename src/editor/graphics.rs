@@ -17,13 +17,22 @@ pub(crate) fn render(
     data: &AppendBuffer,
     append_buffer: &mut AppendBuffer,
 ) {
+    if !context.borrow().listing.is_empty() {
+        // The swap file question is the last line of its own listing
+        let question = context.borrow().pending == Pending::SwapFound;
+        render_listing(terminal, &context.borrow().listing, !question);
+        return;
+    }
     cursor.calculate_row_offset();
     append_buffer.append(b"\x1B[?25l");
     append_buffer.append(b"\x1B[H");
     draw(context, cursor, data, append_buffer);
     draw_status_bar(context, terminal, cursor, data, append_buffer);
     draw_message_bar(context, append_buffer);
-    if context.borrow().h_reg > 0 && context.borrow().mode == EditorModes::Normal {
+    if context.borrow().mode == EditorModes::CommandLine {
+        let typed = context.borrow().command_line.1.chars().count();
+        append_buffer.append_str(format!("\x1B[{};{}H", cursor.rows + 2, typed + 2).as_str());
+    } else if context.borrow().h_reg > 0 && context.borrow().mode == EditorModes::Normal {
         let (i_x, i_y) = file_index_to_cursor(context, data);
         append_buffer.append_str(
             format!(
@@ -47,11 +56,30 @@ pub(crate) fn render(
     append_buffer.write(&mut terminal.borrow_mut().stdout);
 }
 
+pub(crate) fn render_listing(terminal: &Rc<RefCell<Terminal>>, lines: &[String], more: bool) {
+    let mut append_buffer = AppendBuffer::default();
+    append_buffer.append(b"\x1B[2J");
+    append_buffer.append(b"\x1B[H");
+    for line in lines {
+        append_buffer.append_str(line);
+        append_buffer.append(b"\r\n");
+    }
+    if more {
+        append_buffer.append_str("Press ENTER or type command to continue");
+    }
+    append_buffer.write(&mut terminal.borrow_mut().stdout);
+}
+
 pub(crate) fn draw_message_bar(
     context: &Rc<RefCell<EditorContext>>,
     append_buffer: &mut AppendBuffer,
 ) {
     append_buffer.append(b"\x1B[K");
+    if context.borrow().mode == EditorModes::CommandLine {
+        let (kind, text) = &context.borrow().command_line;
+        append_buffer.append_str(&format!("{}{}", kind, text));
+        return;
+    }
     append_buffer.append_str(&context.borrow().status_message);
 }
 pub(crate) fn draw_status_bar(
@@ -65,7 +93,13 @@ pub(crate) fn draw_status_bar(
     //rstatus
     // ROW COUNT
     let mut status = String::new();
-    let mut rstatus: String = format!(" [{}/{}] ", cursor.y() + 1, cursor.rows);
+    let mut rstatus: String = format!(
+        " {} {} [{}/{}] ",
+        context.borrow().options.string("fileencoding"),
+        context.borrow().options.string("fileformat"),
+        cursor.y() + 1,
+        cursor.rows
+    );
     // DATE TIME
     let datetime: DateTime<Utc> = context.borrow().status_message_time.into();
     let dt_string = datetime.format("%T %d/%m/%Y").to_string();
@@ -74,15 +108,30 @@ pub(crate) fn draw_status_bar(
     // PRINT mode
     let mode = match context.borrow().mode {
         EditorModes::Insert => String::from("[--INSERT--]"),
+        EditorModes::Normal if context.borrow().restart_insert => String::from("[--(INSERT)--]"),
         EditorModes::Normal => String::from("[--NORMAL--]"),
+        EditorModes::Replace => String::from("[--REPLACE--]"),
+        EditorModes::Visual => String::from("[--VISUAL--]"),
+        EditorModes::VisualLine => String::from("[--VISUAL LINE--]"),
+        EditorModes::VisualBlock => String::from("[--VISUAL BLOCK--]"),
+        EditorModes::CommandLine => String::from("[--COMMAND--]"),
     };
     status.push_str(&mode);
+    if let Some((name, _)) = context.borrow().input.recording {
+        status.push_str(&format!("[recording @{}]", name));
+    }
     //FILE NAME
     if context.borrow().files.is_empty() {
         status.push_str("[No Name]")
     } else {
         status.push_str(&context.borrow().files);
     }
+    if context.borrow().options.flag("readonly") {
+        status.push_str("[RO]");
+    }
+    if !context.borrow().options.flag("modifiable") {
+        status.push_str("[-]");
+    }
     // DIRTY
     status.push_str(match context.borrow().dirty {
         0 => "",
@@ -117,13 +166,16 @@ pub(crate) fn draw(
 ) {
     for _y in 0..cursor.rows {
         let file_row = _y + cursor.row_offset;
-        let absolute_numbers = &format!(
-            "{:>width$} ",
-            file_row,
-            width = cursor.editor_configs.x_offset - 1
-        )
-        .to_string();
-        append_buffer.append_str(&absolute_numbers);
+        // :set nonumber leaves no room for them
+        if cursor.editor_configs.x_offset > 0 {
+            let absolute_numbers = &format!(
+                "{:>width$} ",
+                file_row,
+                width = cursor.editor_configs.x_offset - 1
+            )
+            .to_string();
+            append_buffer.append_str(absolute_numbers);
+        }
         //cursor.editor_configs.x_offset = absolute_numbers.len();
         if file_row >= data.new_lines.len() && file_row <= data.new_lines.len() {
             append_buffer.append(b"~");
@@ -131,6 +183,30 @@ pub(crate) fn draw(
             // TODO Ref here HANDLE COL limits
             let (index_l, index_r) =
                 cursor.calculate_row_of_insert_indices(file_row as usize, &data.new_lines);
+            let selected = match context.borrow().mode.is_visual() {
+                true => operations::visual::row_columns(
+                    &context.borrow(),
+                    cursor,
+                    file_row,
+                    index_r - index_l,
+                ),
+                false => None,
+            };
+            if let Some((from, to)) = selected {
+                // Selection in reverse video, a selected newline shows as one cell
+                let line = &data.buffer[index_l..index_r];
+                append_text(append_buffer, &line[..from]);
+                append_buffer.append(b"\x1B[7m");
+                append_text(append_buffer, &line[from..to.min(line.len())]);
+                if to > line.len() || (from == to && line.is_empty()) {
+                    append_buffer.append(b" ");
+                }
+                append_buffer.append(b"\x1B[27m");
+                append_text(append_buffer, &line[to.min(line.len())..]);
+                append_buffer.append(b"\x1B[K");
+                append_buffer.append(b"\r\n");
+                continue;
+            }
             // TODO Def very Bad
             let mut v: Vec<(usize, usize)> = Vec::new();
 
@@ -142,32 +218,47 @@ pub(crate) fn draw(
             let mut prev = -1 as i32;
             for (_, (high_l, high_r)) in v.iter().enumerate() {
                 if prev < 0 {
-                    append_buffer.append(&data.buffer[index_l..*high_l]);
+                    append_text(append_buffer, &data.buffer[index_l..*high_l]);
                     append_buffer.append(constants::BIYellow); //YELLOW
                                                                //let offset = constants::BIYellow.len();
-                    append_buffer.append(&data.buffer[*high_l..*high_r]);
+                    append_text(append_buffer, &data.buffer[*high_l..*high_r]);
                     append_buffer.append(constants::Color_Off);
                     append_buffer.append(b"\x1B[0m");
                     //append_buffer
                     //   .append(&data.buffer[*high_r..index_r]);
                 } else {
-                    append_buffer.append(&data.buffer[prev as usize..*high_l]);
+                    append_text(append_buffer, &data.buffer[prev as usize..*high_l]);
                     append_buffer.append(constants::BIYellow); //YELLOW
-                    append_buffer.append(&data.buffer[*high_l..*high_r]);
+                    append_text(append_buffer, &data.buffer[*high_l..*high_r]);
                     append_buffer.append(constants::Color_Off);
                 }
                 prev = *high_r as i32;
             }
             if v.len() == 0 {
-                append_buffer.append(&data.buffer[index_l..index_r]);
+                append_text(append_buffer, &data.buffer[index_l..index_r]);
             } else {
-                append_buffer.append(&data.buffer[prev as usize..index_r]);
+                append_text(append_buffer, &data.buffer[prev as usize..index_r]);
             }
         }
         append_buffer.append(b"\x1B[K");
         append_buffer.append(b"\r\n");
     }
 }
+// Control characters would drive the terminal, they show as their ^ letter in
+// reverse video and still take a single cell
+fn append_text(append_buffer: &mut AppendBuffer, text: &[u8]) {
+    let mut start = 0;
+    for (i, c) in text.iter().enumerate() {
+        if (*c < 0x20 && *c != b'\t') || *c == 0x7F {
+            append_buffer.append(&text[start..i]);
+            append_buffer.append(b"\x1B[7m");
+            append_buffer.append(&[*c ^ 0x40]);
+            append_buffer.append(b"\x1B[27m");
+            start = i + 1;
+        }
+    }
+    append_buffer.append(&text[start..]);
+}
 pub(crate) fn file_index_to_cursor(
     context: &Rc<RefCell<EditorContext>>,
     data: &AppendBuffer,
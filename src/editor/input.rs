@@ -0,0 +1,185 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::editor::Terminal;
+
+// Every key the editor handles comes through the InputQueue. Keys queued by macros,
+// mappings or a script are handed out before the terminal is read again, so all modes
+// see them exactly like typed ones. The mode dispatcher in Editor::process_key_press
+// takes one key at a time and keeps what is half typed (an operator waiting for its
+// motion, a register name, ...) in EditorContext::pending instead of reading ahead.
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    #[default]
+    Terminal,
+    Macro,
+    // The right hand side of a mapping, `remap` is false for :noremap ones
+    Mapping {
+        remap: bool,
+        silent: bool,
+    },
+    Script,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub code: u8,
+    pub source: KeySource,
+}
+
+impl Key {
+    // Mappings apply to it
+    pub(crate) fn remap(&self) -> bool {
+        !matches!(self.source, KeySource::Mapping { remap: false, .. })
+    }
+}
+
+// What the next key completes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Pending {
+    #[default]
+    Nothing,
+    // d, y or c waiting for a motion
+    Operator(u8),
+    GPrefix,
+    // g~, gu or gU waiting for a motion
+    CaseOperator(u8),
+    // r or gr waiting for the character
+    ReplaceChar(&'static str),
+    Register,
+    SetMark,
+    // ` or ' waiting for the mark name
+    JumpToMark(u8),
+    Record,
+    PlayMacro,
+    // A listing is on screen until the next key
+    Listing,
+    // Ctrl-V in insert mode, the next key goes in as it is
+    Literal,
+    // Ctrl-R in insert mode waiting for the register name
+    InsertRegister,
+    // A swap file was found for the file being opened, what to do about it
+    SwapFound,
+}
+
+#[derive(Debug, Default)]
+pub struct InputQueue {
+    pending: VecDeque<Key>,
+    // q{reg} in progress: the register and the keys typed since
+    pub(crate) recording: Option<(char, Vec<u8>)>,
+    // Mappings expanded since a key not typed by a mapping was used
+    pub(crate) depth: usize,
+    // Where the key being handled came from
+    pub(crate) source: KeySource,
+}
+
+impl InputQueue {
+    // Keys that play before anything already queued, a macro calling another macro
+    // finishes the inner one first
+    pub(crate) fn push_front(&mut self, keys: &[u8], source: KeySource) {
+        for code in keys.iter().rev() {
+            self.pending.push_front(Key {
+                code: *code,
+                source,
+            });
+        }
+    }
+    // Keys that play after everything queued, like a script given at startup
+    pub(crate) fn push_back(&mut self, keys: &[u8], source: KeySource) {
+        self.pending.extend(keys.iter().map(|code| Key {
+            code: *code,
+            source,
+        }));
+    }
+    // Keys taken to look for a mapping that turned out not to be part of it
+    pub(crate) fn unread(&mut self, keys: &[Key]) {
+        for key in keys.iter().rev() {
+            self.pending.push_front(*key);
+        }
+    }
+    // Blocks on the terminal only when nothing is queued
+    pub(crate) fn next(&mut self, terminal: &Rc<RefCell<Terminal>>) -> Option<Key> {
+        if let Some(key) = self.pending.pop_front() {
+            return Some(key);
+        }
+        Some(Key {
+            code: terminal.borrow_mut().read_key()?,
+            source: KeySource::Terminal,
+        })
+    }
+    // Like next but gives up when nothing is typed within `timeout`
+    pub(crate) fn next_within(
+        &mut self,
+        terminal: &Rc<RefCell<Terminal>>,
+        timeout: Duration,
+    ) -> Option<Key> {
+        if let Some(key) = self.pending.pop_front() {
+            return Some(key);
+        }
+        Some(Key {
+            code: terminal.borrow_mut().read_key_within(timeout)?,
+            source: KeySource::Terminal,
+        })
+    }
+    // Keys handed to the editor, after mappings got their pick. Only typed keys are
+    // recorded, a macro played while recording stays @x and a mapping stays its lhs.
+    pub(crate) fn consume(&mut self, keys: &[Key]) {
+        if keys
+            .iter()
+            .any(|key| !matches!(key.source, KeySource::Mapping { .. }))
+        {
+            self.depth = 0;
+        }
+        if let Some((_, recorded)) = self.recording.as_mut() {
+            recorded.extend(
+                keys.iter()
+                    .filter(|key| matches!(key.source, KeySource::Terminal | KeySource::Script))
+                    .map(|key| key.code),
+            );
+        }
+    }
+    // The next key comes from a <silent> mapping, nothing to draw in between
+    pub(crate) fn silent(&self) -> bool {
+        matches!(
+            self.pending.front(),
+            Some(Key {
+                source: KeySource::Mapping { silent: true, .. },
+                ..
+            })
+        )
+    }
+    // Nothing queued, the next key is waited for at the terminal
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+    // A failing command drops the rest of the macro or mapping that typed it
+    pub(crate) fn abort(&mut self) {
+        self.pending
+            .retain(|key| matches!(key.source, KeySource::Terminal | KeySource::Script));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::testing::{editor, type_script};
+
+    #[test]
+    fn dd_deletes_the_line() {
+        let mut editor = editor(b"one\ntwo\nthree\n");
+        type_script(&mut editor, b"jdd");
+        assert_eq!(editor.data.buffer, b"one\nthree\n");
+        assert_eq!(editor.cursor.borrow().absy(), 1);
+    }
+
+    #[test]
+    fn yy_yanks_into_a_named_register() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"j\"ayy");
+        let register = editor.context.borrow().registers.get(Some('a'), "");
+        assert_eq!(register.map(|r| r.text), Some(b"two\n".to_vec()));
+        assert_eq!(editor.data.buffer, b"one\ntwo\n");
+    }
+}
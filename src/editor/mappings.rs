@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+
+// Key mappings, one trie per mode. A key sequence walks down the trie: a node with
+// children means a longer mapping may still come, so Editor::next_key waits up to
+// timeoutlen for the next key before settling on the longest mapping typed so far.
+// Left and right hand sides are stored as raw bytes, <CR> and friends are translated
+// when the mapping is defined and back when it is listed. Insert mode abbreviations
+// are kept as one more trie, looked up by Editor::expand_abbreviation.
+
+// Expansions without a key consumed in between before a mapping counts as recursive
+pub const MAX_MAP_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MapMode {
+    Normal,
+    Visual,
+    OperatorPending,
+    Insert,
+    CommandLine,
+    Abbreviation,
+}
+
+impl MapMode {
+    const ALL: [MapMode; 6] = [
+        MapMode::Normal,
+        MapMode::Visual,
+        MapMode::OperatorPending,
+        MapMode::Insert,
+        MapMode::CommandLine,
+        MapMode::Abbreviation,
+    ];
+    // The column :map listings show
+    fn name(&self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::OperatorPending => 'o',
+            MapMode::Insert | MapMode::Abbreviation => 'i',
+            MapMode::CommandLine => 'c',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub rhs: Vec<u8>,
+    pub noremap: bool,
+    pub silent: bool,
+    // Taken as soon as it is typed even when a longer mapping starts with it
+    pub nowait: bool,
+}
+
+#[derive(Debug, Default)]
+struct Trie {
+    children: BTreeMap<u8, Trie>,
+    mapping: Option<Mapping>,
+}
+
+impl Trie {
+    fn node(&self, keys: &[u8]) -> Option<&Trie> {
+        keys.iter()
+            .try_fold(self, |node, key| node.children.get(key))
+    }
+    fn insert(&mut self, lhs: &[u8], mapping: Mapping) {
+        let node = lhs
+            .iter()
+            .fold(self, |node, key| node.children.entry(*key).or_default());
+        node.mapping = Some(mapping);
+    }
+    // Drops the nodes left without mappings on the way back up
+    fn remove(&mut self, lhs: &[u8]) -> Option<Mapping> {
+        let Some((first, rest)) = lhs.split_first() else {
+            return self.mapping.take();
+        };
+        let child = self.children.get_mut(first)?;
+        let removed = child.remove(rest);
+        if child.mapping.is_none() && child.children.is_empty() {
+            self.children.remove(first);
+        }
+        removed
+    }
+    fn collect(&self, prefix: &mut Vec<u8>, out: &mut Vec<(Vec<u8>, Mapping)>) {
+        if let Some(mapping) = &self.mapping {
+            out.push((prefix.clone(), mapping.clone()));
+        }
+        for (key, child) in self.children.iter() {
+            prefix.push(*key);
+            child.collect(prefix, out);
+            prefix.pop();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Mappings {
+    modes: BTreeMap<MapMode, Trie>,
+    // What <Leader> stands for, read when a mapping is defined like vim's mapleader
+    pub leader: Vec<u8>,
+}
+
+impl Default for Mappings {
+    fn default() -> Self {
+        Self {
+            modes: BTreeMap::new(),
+            leader: b"\\".to_vec(),
+        }
+    }
+}
+
+// A :map family command split into what it does and for which modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapCommand {
+    Map { noremap: bool },
+    Unmap,
+    Clear,
+}
+
+impl Mappings {
+    // The modes and action of `:nnoremap`, `:iunmap`, `:map!` ... None when `name`
+    // isn't a mapping command
+    pub(crate) fn parse_command(name: &str) -> Option<(MapCommand, Vec<MapMode>)> {
+        let (name, bang) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let command = match name {
+            "map" | "nm" | "nmap" | "vm" | "vmap" | "xm" | "xmap" | "om" | "omap" | "im"
+            | "imap" | "cm" | "cmap" => MapCommand::Map { noremap: false },
+            "no" | "noremap" | "nn" | "nnoremap" | "vn" | "vnoremap" | "xn" | "xnoremap"
+            | "ono" | "onoremap" | "ino" | "inoremap" | "cno" | "cnoremap" => {
+                MapCommand::Map { noremap: true }
+            }
+            "unm" | "unmap" | "nun" | "nunmap" | "vu" | "vunmap" | "xu" | "xunmap" | "ou"
+            | "ounmap" | "iu" | "iunmap" | "cu" | "cunmap" => MapCommand::Unmap,
+            "mapc" | "mapclear" | "nmapc" | "nmapclear" | "vmapc" | "vmapclear" | "xmapc"
+            | "xmapclear" | "omapc" | "omapclear" | "imapc" | "imapclear" | "cmapc"
+            | "cmapclear" => MapCommand::Clear,
+            // Abbreviations only work in insert mode, :ab is :iab
+            "ab" | "abbreviate" | "iab" | "iabbrev" => {
+                return Some((
+                    MapCommand::Map { noremap: false },
+                    vec![MapMode::Abbreviation],
+                ))
+            }
+            "norea" | "noreabbrev" | "inorea" | "inoreabbrev" => {
+                return Some((
+                    MapCommand::Map { noremap: true },
+                    vec![MapMode::Abbreviation],
+                ))
+            }
+            "una" | "unabbreviate" | "iuna" | "iunabbrev" => {
+                return Some((MapCommand::Unmap, vec![MapMode::Abbreviation]))
+            }
+            "abc" | "abclear" | "iabc" | "iabclear" => {
+                return Some((MapCommand::Clear, vec![MapMode::Abbreviation]))
+            }
+            _ => return None,
+        };
+        let modes = match (name.chars().next(), bang) {
+            // :map! and friends are for insert and command line mode
+            (_, true) => vec![MapMode::Insert, MapMode::CommandLine],
+            (Some('n'), _) if name != "no" && name != "noremap" => vec![MapMode::Normal],
+            (Some('v' | 'x'), _) => vec![MapMode::Visual],
+            (Some('o'), _) => vec![MapMode::OperatorPending],
+            (Some('i'), _) => vec![MapMode::Insert],
+            (Some('c'), _) => vec![MapMode::CommandLine],
+            _ => vec![MapMode::Normal, MapMode::Visual, MapMode::OperatorPending],
+        };
+        Some((command, modes))
+    }
+    pub(crate) fn insert(&mut self, mode: MapMode, lhs: &[u8], mapping: Mapping) {
+        self.modes.entry(mode).or_default().insert(lhs, mapping);
+    }
+    pub(crate) fn remove(&mut self, mode: MapMode, lhs: &[u8]) -> Option<Mapping> {
+        self.modes.get_mut(&mode)?.remove(lhs)
+    }
+    pub(crate) fn clear(&mut self, mode: MapMode) {
+        self.modes.remove(&mode);
+    }
+    pub(crate) fn get(&self, mode: MapMode, lhs: &[u8]) -> Option<&Mapping> {
+        self.modes.get(&mode)?.node(lhs)?.mapping.as_ref()
+    }
+    // Some mapping is longer than `keys` and starts with them
+    pub(crate) fn has_longer(&self, mode: MapMode, keys: &[u8]) -> bool {
+        self.modes
+            .get(&mode)
+            .and_then(|trie| trie.node(keys))
+            .is_some_and(|node| !node.children.is_empty())
+    }
+    // The longest mapping `keys` start with and how many keys it takes
+    pub(crate) fn longest(&self, mode: MapMode, keys: &[u8]) -> Option<(usize, &Mapping)> {
+        let mut node = self.modes.get(&mode)?;
+        let mut found = None;
+        for (i, key) in keys.iter().enumerate() {
+            node = match node.children.get(key) {
+                Some(child) => child,
+                None => break,
+            };
+            if let Some(mapping) = &node.mapping {
+                found = Some((i + 1, mapping));
+            }
+        }
+        found
+    }
+    // Lines for :map, only mappings starting with `prefix` when one is given
+    pub(crate) fn listing(&self, modes: &[MapMode], prefix: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for mode in MapMode::ALL.iter().filter(|m| modes.contains(m)) {
+            let Some(node) = self.modes.get(mode).and_then(|trie| trie.node(prefix)) else {
+                continue;
+            };
+            let mut found = Vec::new();
+            node.collect(&mut prefix.to_vec(), &mut found);
+            for (lhs, mapping) in found {
+                lines.push(format!(
+                    "{}  {:<12} {}{} {}",
+                    mode.name(),
+                    key_notation(&lhs),
+                    if mapping.noremap { '*' } else { ' ' },
+                    if mapping.silent { '@' } else { ' ' },
+                    key_notation(&mapping.rhs)
+                ));
+            }
+        }
+        lines
+    }
+}
+
+// Bytes of a key sequence written like `<Leader>w`, `<C-w>j` or `:w<CR>`
+pub(crate) fn parse_keys(text: &str, leader: &[u8]) -> Vec<u8> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let special = match c {
+            '<' => rest.find('>').and_then(|end| {
+                let key = special_key(&rest[1..end], leader)?;
+                Some((key, end + 1))
+            }),
+            _ => None,
+        };
+        match special {
+            Some((key, len)) => {
+                keys.extend(key);
+                rest = &rest[len..];
+            }
+            None => {
+                let mut buf = [0u8; 4];
+                keys.extend(c.encode_utf8(&mut buf).as_bytes());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    keys
+}
+
+fn special_key(name: &str, leader: &[u8]) -> Option<Vec<u8>> {
+    let lower = name.to_ascii_lowercase();
+    let key = match lower.as_str() {
+        "cr" | "enter" | "return" => b'\r',
+        "nl" => b'\n',
+        "esc" => 0x1B,
+        "tab" => b'\t',
+        "bs" => 0x7F,
+        "space" => b' ',
+        "lt" => b'<',
+        "bar" => b'|',
+        "bslash" => b'\\',
+        "leader" => return Some(leader.to_vec()),
+        "nop" => return Some(Vec::new()),
+        _ => match lower.strip_prefix("c-") {
+            Some(c) if c.len() == 1 && c.as_bytes()[0].is_ascii_alphabetic() => {
+                c.as_bytes()[0] & 0x1f
+            }
+            _ => return None,
+        },
+    };
+    Some(vec![key])
+}
+
+// Inverse of parse_keys for listings
+pub(crate) fn key_notation(keys: &[u8]) -> String {
+    if keys.is_empty() {
+        return String::from("<Nop>");
+    }
+    let mut text = String::new();
+    for key in String::from_utf8_lossy(keys).chars() {
+        match key {
+            '\r' => text.push_str("<CR>"),
+            '\n' => text.push_str("<NL>"),
+            '\x1B' => text.push_str("<Esc>"),
+            '\t' => text.push_str("<Tab>"),
+            '\x7F' => text.push_str("<BS>"),
+            ' ' => text.push_str("<Space>"),
+            c if (c as u32) < 0x20 => text.push_str(&format!("<C-{}>", ((c as u8) | 0x60) as char)),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::{editor, type_script};
+
+    fn mapping(rhs: &[u8]) -> Mapping {
+        Mapping {
+            rhs: rhs.to_vec(),
+            noremap: false,
+            silent: false,
+            nowait: false,
+        }
+    }
+
+    #[test]
+    fn trie_tells_prefixes_exact_and_ambiguous_keys_apart() {
+        let mut mappings = Mappings::default();
+        mappings.insert(MapMode::Normal, b"ab", mapping(b"x"));
+        // Only a prefix
+        assert!(mappings.has_longer(MapMode::Normal, b"a"));
+        assert!(mappings.get(MapMode::Normal, b"a").is_none());
+        // Exact
+        assert_eq!(mappings.get(MapMode::Normal, b"ab"), Some(&mapping(b"x")));
+        assert!(!mappings.has_longer(MapMode::Normal, b"ab"));
+        // Ambiguous, a is a mapping and the start of ab
+        mappings.insert(MapMode::Normal, b"a", mapping(b"y"));
+        assert!(mappings.has_longer(MapMode::Normal, b"a"));
+        assert_eq!(
+            mappings.longest(MapMode::Normal, b"ac").map(|(n, _)| n),
+            Some(1)
+        );
+        assert_eq!(
+            mappings.longest(MapMode::Normal, b"abc").map(|(n, _)| n),
+            Some(2)
+        );
+        assert!(mappings.longest(MapMode::Insert, b"ab").is_none());
+    }
+
+    #[test]
+    fn removing_prunes_the_trie() {
+        let mut mappings = Mappings::default();
+        mappings.insert(MapMode::Normal, b"abc", mapping(b"x"));
+        assert_eq!(
+            mappings.remove(MapMode::Normal, b"abc"),
+            Some(mapping(b"x"))
+        );
+        assert!(!mappings.has_longer(MapMode::Normal, b"a"));
+        assert_eq!(mappings.remove(MapMode::Normal, b"abc"), None);
+    }
+
+    #[test]
+    fn parse_keys_reads_special_keys() {
+        assert_eq!(parse_keys("<Leader>w", b","), b",w");
+        assert_eq!(parse_keys(":w<CR>", b"\\"), b":w\r");
+        assert_eq!(parse_keys("<C-w>j<esc>", b"\\"), b"\x17j\x1B");
+        assert_eq!(parse_keys("<lt>x>", b"\\"), b"<x>");
+        // Not a key name, taken as it is
+        assert_eq!(parse_keys("<foo>", b"\\"), b"<foo>");
+        assert_eq!(parse_keys("<Nop>", b"\\"), b"");
+    }
+
+    #[test]
+    fn key_notation_round_trips() {
+        for text in [":w<CR>", "<C-w>j", "<Esc><Tab><BS><Space>", "<Nop>", "é"] {
+            assert_eq!(key_notation(&parse_keys(text, b"\\")), text);
+        }
+    }
+
+    #[test]
+    fn map_commands_pick_their_modes() {
+        let normal = Mappings::parse_command("nnoremap");
+        assert_eq!(
+            normal,
+            Some((MapCommand::Map { noremap: true }, vec![MapMode::Normal]))
+        );
+        let bang = Mappings::parse_command("map!").unwrap();
+        assert_eq!(bang.1, vec![MapMode::Insert, MapMode::CommandLine]);
+        assert_eq!(
+            Mappings::parse_command("iunmap").unwrap().0,
+            MapCommand::Unmap
+        );
+        assert!(Mappings::parse_command("nope").is_none());
+    }
+
+    #[test]
+    fn mapped_keys_run_their_rhs() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b":nmap Q dd\r");
+        type_script(&mut editor, b"Q");
+        assert_eq!(editor.data.buffer, b"two\n");
+        // noremap isn't expanded again, Q here is the plain key
+        type_script(&mut editor, b":nnoremap X Qx\r:nmap Q <Nop>\rX");
+        assert_eq!(editor.data.buffer, b"wo\n");
+    }
+
+    #[test]
+    fn abbreviation_expands_after_the_word() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b":iab teh the\r");
+        type_script(&mut editor, b"iteh teh.teh\x1B");
+        assert_eq!(editor.data.buffer, b"the the.the\n");
+    }
+
+    #[test]
+    fn abbreviation_needs_the_whole_word_and_no_ctrl_v() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b":iab teh the\r");
+        type_script(&mut editor, b"iateh teh\x16 \x1B");
+        assert_eq!(editor.data.buffer, b"ateh teh \n");
+    }
+
+    #[test]
+    fn unabbreviate_removes_it() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b":iab teh the\r:una teh\r");
+        assert_eq!(editor.context.borrow().status_message, "");
+        type_script(&mut editor, b"iteh \x1B");
+        assert_eq!(editor.data.buffer, b"teh \n");
+        type_script(&mut editor, b":una teh\r");
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E24: No such abbreviation"
+        );
+    }
+
+    #[test]
+    fn abbreviation_lhs_must_be_a_keyword() {
+        let mut editor = editor(b"\n");
+        type_script(&mut editor, b":iab t-h the\r");
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E474: Invalid argument"
+        );
+        type_script(&mut editor, b"it-h \x1B");
+        assert_eq!(editor.data.buffer, b"t-h \n");
+    }
+}
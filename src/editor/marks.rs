@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+
+// Marks remember byte offsets into the buffer:
+//  a-z       set with m, local to the buffer
+//  A-Z       set with m, remember the file too so they work from anywhere
+//  '         where the cursor was before the latest jump
+//  . ^       last change, where insert mode was left
+//  [ ]       first and last character of the last change or yank
+//  < >       start and end of the last visual selection
+// The buffer keeps a journal of its edits (see AppendBuffer::edits) and apply() moves
+// every mark past text inserted or deleted in front of it. A mark whose whole line
+// got deleted goes away, one inside a partial delete moves to where the delete was.
+// The jump list and the change list live here too and move along the same way.
+
+const JUMP_LIST_LEN: usize = 100;
+const CHANGE_LIST_LEN: usize = 100;
+// A change this close to the newest entry on the same line replaces it, like vim does
+// with 'textwidth' unset
+const CHANGE_MERGE_COLUMNS: usize = 79;
+
+// One splice of the buffer: `removed` were the bytes at `at`, `inserted` bytes replaced them
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub at: usize,
+    pub removed: Vec<u8>,
+    pub inserted: usize,
+    // `at` was the start of a line
+    pub line_start: bool,
+}
+
+impl Edit {
+    // Where a position from before the edit is afterwards
+    pub(crate) fn shift(&self, pos: usize) -> Option<usize> {
+        let end = self.at + self.removed.len();
+        if pos < self.at {
+            return Some(pos);
+        }
+        if pos >= end {
+            return Some(pos - self.removed.len() + self.inserted);
+        }
+        let (before, after) = self.removed.split_at(pos - self.at);
+        let line_gone = (self.line_start || before.contains(&b'\n')) && after.contains(&b'\n');
+        match line_gone {
+            true => None,
+            false => Some(self.at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub file: String,
+    pub offset: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Marks {
+    local: HashMap<char, usize>,
+    file: HashMap<char, Position>,
+    jumps: Vec<Position>,
+    // Entry Ctrl-O/Ctrl-I stand on, jumps.len() when not walking the list
+    jump_index: usize,
+    // Where edits happened, oldest first, walked with g; and g,
+    changes: Vec<usize>,
+    change_index: usize,
+}
+
+// Line of a byte offset, like Cursor::set_file_index
+pub(crate) fn line_of(new_lines: &[i32], offset: usize) -> usize {
+    new_lines.partition_point(|nl| (*nl as usize) < offset)
+}
+
+impl Marks {
+    pub(crate) fn is_valid(name: char) -> bool {
+        name.is_ascii_alphabetic() || "'`.^[]<>".contains(name)
+    }
+    pub(crate) fn set(&mut self, name: char, offset: usize, file: &str) {
+        match name {
+            'A'..='Z' => {
+                let position = Position {
+                    file: file.to_string(),
+                    offset,
+                };
+                self.file.insert(name, position);
+            }
+            '`' => {
+                self.local.insert('\'', offset);
+            }
+            _ => {
+                self.local.insert(name, offset);
+            }
+        }
+    }
+    pub(crate) fn get(&self, name: char, file: &str) -> Option<Position> {
+        match name {
+            'A'..='Z' => self.file.get(&name).cloned(),
+            _ => {
+                let name = if name == '`' { '\'' } else { name };
+                self.local.get(&name).map(|offset| Position {
+                    file: file.to_string(),
+                    offset: *offset,
+                })
+            }
+        }
+    }
+    // Marks of another buffer mean nothing in this one
+    pub(crate) fn clear_local(&mut self) {
+        self.local.clear();
+        self.changes.clear();
+        self.change_index = 0;
+    }
+    // Moves every mark of `file` through the edits, oldest edit first. The edits also
+    // become the . [ and ] marks; typing in insert mode keeps growing the same [ ] span.
+    pub(crate) fn apply(&mut self, edits: &[Edit], file: &str, inserting: bool, new_lines: &[i32]) {
+        let Some(newest) = edits.last() else {
+            return;
+        };
+        let mut span = match inserting {
+            true => self
+                .local
+                .get(&'[')
+                .copied()
+                .zip(self.local.get(&']').copied()),
+            false => None,
+        };
+        for edit in edits {
+            self.local.retain(|_, offset| match edit.shift(*offset) {
+                Some(shifted) => {
+                    *offset = shifted;
+                    true
+                }
+                None => false,
+            });
+            self.file.retain(|_, position| {
+                if position.file != file {
+                    return true;
+                }
+                match edit.shift(position.offset) {
+                    Some(shifted) => {
+                        position.offset = shifted;
+                        true
+                    }
+                    None => false,
+                }
+            });
+            let mut i = 0;
+            while i < self.jumps.len() {
+                let position = &mut self.jumps[i];
+                if position.file == file {
+                    match edit.shift(position.offset) {
+                        Some(shifted) => position.offset = shifted,
+                        None => {
+                            self.jumps.remove(i);
+                            if i < self.jump_index {
+                                self.jump_index -= 1;
+                            }
+                            continue;
+                        }
+                    }
+                }
+                i += 1;
+            }
+            // The change list keeps its entries, they just move to the edit
+            for offset in self.changes.iter_mut() {
+                *offset = edit.shift(*offset).unwrap_or(edit.at);
+            }
+            let end = edit.at + edit.inserted.saturating_sub(1);
+            span = match span {
+                Some((first, last)) => {
+                    let first = edit.shift(first).unwrap_or(edit.at);
+                    let last = edit.shift(last).unwrap_or(edit.at);
+                    // Only an edit touching the span extends it
+                    match edit.at <= last + 1 && end + 1 >= first {
+                        true => Some((first.min(edit.at), last.max(end))),
+                        false => Some((edit.at, end)),
+                    }
+                }
+                None => Some((edit.at, end)),
+            };
+        }
+        if let Some((first, last)) = span {
+            self.local.insert('[', first);
+            self.local.insert(']', last);
+        }
+        self.local.insert('.', newest.at);
+        self.push_change(newest.at, new_lines);
+    }
+    fn push_change(&mut self, offset: usize, new_lines: &[i32]) {
+        let line = line_of(new_lines, offset);
+        let line_begin = |line: usize| match line {
+            0 => 0,
+            _ => new_lines[line - 1] as usize + 1,
+        };
+        let merge = self.changes.last().is_some_and(|last| {
+            line_of(new_lines, *last) == line
+                && (*last - line_begin(line)).abs_diff(offset - line_begin(line))
+                    <= CHANGE_MERGE_COLUMNS
+        });
+        if merge {
+            self.changes.pop();
+        }
+        self.changes.push(offset);
+        if self.changes.len() > CHANGE_LIST_LEN {
+            self.changes.remove(0);
+        }
+        self.change_index = self.changes.len();
+    }
+    // g; with a negative step, g, with a positive one. The error is vim's message.
+    pub(crate) fn walk_changes(&mut self, step: isize) -> Result<usize, &'static str> {
+        if self.changes.is_empty() {
+            return Err("E664: changelist is empty");
+        }
+        if step < 0 && self.change_index == 0 {
+            return Err("E662: At start of changelist");
+        }
+        if step > 0 && self.change_index + 1 >= self.changes.len() {
+            return Err("E663: At end of changelist");
+        }
+        let index = self.change_index as isize + step;
+        self.change_index = index.clamp(0, self.changes.len() as isize - 1) as usize;
+        Ok(self.changes[self.change_index])
+    }
+    pub(crate) fn changes(&self) -> (&[usize], usize) {
+        (&self.changes, self.change_index)
+    }
+    // Called before a jump with the position being left. Older entries for the same
+    // line are dropped so the list holds each line once.
+    pub(crate) fn push_jump(&mut self, position: Position, new_lines: &[i32]) {
+        let line = line_of(new_lines, position.offset);
+        self.jumps
+            .retain(|j| j.file != position.file || line_of(new_lines, j.offset) != line);
+        self.jumps.push(position);
+        if self.jumps.len() > JUMP_LIST_LEN {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+    // Ctrl-O. The first step back remembers where it started so Ctrl-I can return.
+    pub(crate) fn jump_back(&mut self, current: Position, new_lines: &[i32]) -> Option<Position> {
+        if self.jump_index == 0 {
+            return None;
+        }
+        if self.jump_index == self.jumps.len() {
+            self.push_jump(current, new_lines);
+            self.jump_index = self.jumps.len() - 1;
+            if self.jump_index == 0 {
+                return None;
+            }
+        }
+        self.jump_index -= 1;
+        self.jumps.get(self.jump_index).cloned()
+    }
+    // Ctrl-I
+    pub(crate) fn jump_forward(&mut self) -> Option<Position> {
+        if self.jump_index + 1 >= self.jumps.len() {
+            return None;
+        }
+        self.jump_index += 1;
+        self.jumps.get(self.jump_index).cloned()
+    }
+    pub(crate) fn jumps(&self) -> (&[Position], usize) {
+        (&self.jumps, self.jump_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::{editor, type_script};
+
+    fn edit(at: usize, removed: &[u8], inserted: usize, line_start: bool) -> Edit {
+        Edit {
+            at,
+            removed: removed.to_vec(),
+            inserted,
+            line_start,
+        }
+    }
+
+    #[test]
+    fn edit_shifts_positions_after_it() {
+        let edit = edit(4, b"ab", 5, false);
+        assert_eq!(edit.shift(3), Some(3));
+        assert_eq!(edit.shift(6), Some(9));
+        // Inside a delete within the line the mark moves to where it was
+        assert_eq!(edit.shift(5), Some(4));
+    }
+
+    #[test]
+    fn edit_removing_the_whole_line_drops_the_mark() {
+        let edit = edit(4, b"two\n", 0, true);
+        assert_eq!(edit.shift(5), None);
+        assert_eq!(edit.shift(8), Some(4));
+    }
+
+    #[test]
+    fn mark_moves_up_after_a_delete_above_it() {
+        let mut editor = editor(b"one\ntwo\n  three\n");
+        type_script(&mut editor, b"jjmakkdd");
+        let mark = editor.context.borrow().marks.get('a', "").unwrap();
+        assert_eq!(mark.offset, 4);
+        type_script(&mut editor, b"'a");
+        assert_eq!(editor.cursor.borrow().absy(), 1);
+        assert_eq!(editor.cursor.borrow().absx(), 2);
+    }
+
+    #[test]
+    fn mark_on_a_deleted_line_is_gone() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"jmadd");
+        assert!(editor.context.borrow().marks.get('a', "").is_none());
+    }
+
+    #[test]
+    fn ctrl_o_and_ctrl_i_walk_the_jumps() {
+        let mut editor = editor(b"one\ntwo\nthree\n");
+        type_script(&mut editor, b"jjmakk`a");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+        type_script(&mut editor, b"\x0F");
+        assert_eq!(editor.cursor.borrow().absy(), 0);
+        type_script(&mut editor, b"\t");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+        // '' goes back to where the last jump started
+        type_script(&mut editor, b"''");
+        assert_eq!(editor.cursor.borrow().absy(), 0);
+    }
+
+    #[test]
+    fn g_semicolon_and_g_comma_walk_the_changes() {
+        let mut editor = editor(b"one\ntwo\nthree\n");
+        type_script(&mut editor, b"xjjxk");
+        type_script(&mut editor, b"g;");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+        type_script(&mut editor, b"g;");
+        assert_eq!(editor.cursor.borrow().absy(), 0);
+        type_script(&mut editor, b"g;");
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E662: At start of changelist"
+        );
+        type_script(&mut editor, b"g,");
+        assert_eq!(editor.cursor.borrow().absy(), 2);
+    }
+
+    #[test]
+    fn changes_close_on_one_line_merge() {
+        let mut marks = Marks::default();
+        let new_lines = [3, 7];
+        marks.push_change(0, &new_lines);
+        marks.push_change(2, &new_lines);
+        marks.push_change(5, &new_lines);
+        assert_eq!(marks.changes(), (&[2, 5][..], 2));
+        assert_eq!(marks.walk_changes(1), Err("E663: At end of changelist"));
+        assert_eq!(
+            Marks::default().walk_changes(-1),
+            Err("E664: changelist is empty")
+        );
+    }
+}
@@ -1,23 +1,41 @@
 use chrono::offset::Utc;
 use chrono::DateTime;
 use std::cell::RefCell;
-use std::io::{BufReader, BufWriter, Read, Stdin, Stdout, Write};
+use std::io::{BufReader, Read, Stdin, Stdout, Write};
 use std::rc::Rc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 mod buffer;
+mod clipboard;
 mod constants;
 mod cursor;
 mod engine;
+mod files;
 mod graphics;
+mod input;
+mod mappings;
+mod marks;
+mod options;
+mod register;
+mod swap;
 mod terminal;
+#[cfg(test)]
+mod testing;
 mod window;
 
 pub use crate::editor::constants::*;
 
 pub use crate::editor::buffer::AppendBuffer;
+pub use crate::editor::clipboard::Clipboard;
 pub use crate::editor::cursor::Cursor;
 pub use crate::editor::engine::*;
+pub use crate::editor::files::FileStamp;
+pub use crate::editor::input::*;
+pub use crate::editor::mappings::*;
+pub use crate::editor::marks::*;
+pub use crate::editor::options::*;
+pub use crate::editor::register::*;
+pub use crate::editor::swap::{list_swap_files, FoundSwap, SwapFile};
 pub use crate::editor::terminal::Terminal;
 pub use crate::editor::window::Window;
 
@@ -42,6 +60,9 @@ pub struct EditorConfigs {
     pub y_offset: usize,
 }
 
+// Kind, anchor and cursor end of a visual selection, kept for gv and '< '>
+pub type VisualSelection = (EditorModes, (usize, usize), (usize, usize));
+
 pub struct EditorContext {
     pub mode: EditorModes,
     pub state: EditorHealth,
@@ -52,6 +73,44 @@ pub struct EditorContext {
     pub line_reg: usize,
     pub dirty: i8,
     pub files: String,
+    // Files given on the command line and which of them is loaded, for :next and :prev
+    pub arglist: Vec<String>,
+    pub arg_index: usize,
+    // The file as it was last read or written, and whether its changing since has
+    // been reported
+    pub file_stamp: Option<FileStamp>,
+    pub stamp_warned: bool,
+    pub swap: Option<SwapFile>,
+    // Someone else's swap file, kept while asking what to do about it
+    pub swap_found: Option<FoundSwap>,
+    pub last_change: Option<Rc<RefCell<Operator>>>,
+    pub insert_change: Option<Rc<RefCell<Operator>>>,
+    pub registers: Registers,
+    pub marks: Marks,
+    pub input: InputQueue,
+    pub mappings: Mappings,
+    pub options: Options,
+    // Half typed command the next key completes
+    pub pending: Pending,
+    // What : or / has typed so far, the char being which of the two
+    pub command_line: (char, String),
+    // Output of :registers and friends, on screen until a key is pressed
+    pub listing: Vec<String>,
+    pub clipboard: Clipboard,
+    pub visual_start: (usize, usize),
+    pub last_visual: Option<VisualSelection>,
+    pub visual_to_eol: bool,
+    pub block_insert: Option<operations::visual::BlockInsert>,
+    // Ctrl-O in insert mode, back to insert once the normal mode command is done
+    pub restart_insert: bool,
+    // What each key typed in replace mode overwrote, for backspace
+    pub replace_backup: Vec<Option<u8>>,
+}
+impl EditorContext {
+    // Count typed before a command, 1 when none was given
+    pub(crate) fn count(&self) -> usize {
+        self.line_reg.max(1)
+    }
 }
 
 pub struct Editor {
@@ -75,6 +134,29 @@ impl Editor {
             h_reg: 0,
             line_reg: 0,
             files: "".to_string(),
+            arglist: Vec::new(),
+            arg_index: 0,
+            file_stamp: None,
+            stamp_warned: false,
+            swap: None,
+            swap_found: None,
+            last_change: None,
+            insert_change: None,
+            registers: Registers::default(),
+            marks: Marks::default(),
+            input: InputQueue::default(),
+            mappings: Mappings::default(),
+            options: Options::default(),
+            pending: Pending::default(),
+            command_line: (':', String::new()),
+            listing: Vec::new(),
+            clipboard: Clipboard::detect(),
+            visual_start: (0, 0),
+            last_visual: None,
+            visual_to_eol: false,
+            block_insert: None,
+            restart_insert: false,
+            replace_backup: Vec::new(),
         };
         Self {
             cursor: Cursor::new(),
@@ -93,17 +175,76 @@ impl Editor {
         self.window.as_mut().map(|w| w.set_window_size());
         self.cursor.borrow_mut().rows -= 2;
         self.cursor.borrow_mut().editor_configs = self.editor_configs.clone();
+        self.apply_options();
+    }
+    // Options the cursor and screen keep their own copy of
+    pub(crate) fn apply_options(&mut self) {
+        let x_offset = {
+            let options = &self.context.borrow().options;
+            match options.flag("number") {
+                true => options.number("numberwidth"),
+                false => 0,
+            }
+        };
+        self.editor_configs.x_offset = x_offset;
+        self.cursor.borrow_mut().editor_configs.x_offset = x_offset;
+        // :set noswapfile lets go of it, turning it back on waits for the next file
+        if !self.swap_wanted() {
+            self.remove_swap();
+        }
+    }
+    // The yimrc runs before any file is opened, a missing one is fine
+    pub(crate) fn source_yimrc(&mut self) {
+        let Some(path) = yimrc_path() else {
+            return;
+        };
+        if path.exists() {
+            if let Err(err) = self.source(&path.to_string_lossy()) {
+                log::error!("cannot read {}: {err}", path.display());
+            }
+        }
+    }
+    // Runs every line of `path` as an ex command. Blank lines and lines starting with "
+    // are skipped, what the commands report is listed with its line number.
+    pub(crate) fn source(&mut self, path: &str) -> std::io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let mut messages = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let command = line.trim_start().trim_start_matches(':');
+            if command.is_empty() || command.starts_with('"') {
+                continue;
+            }
+            self.context.borrow_mut().status_message.clear();
+            self.execute_command(command);
+            let message = std::mem::take(&mut self.context.borrow_mut().status_message);
+            if !message.is_empty() {
+                messages.push(format!("line {:>4}:", i + 1));
+                messages.push(message);
+            }
+        }
+        if !messages.is_empty() {
+            messages.insert(0, format!("Error detected while processing {}:", path));
+            self.show_listing(messages);
+        }
+        Ok(())
     }
     pub(crate) fn launch_engine(&mut self) {
         loop {
+            // Coming back to the keyboard is when another program may have been at work
+            if self.context.borrow().input.is_empty() {
+                self.check_file();
+            }
             log::debug!("Mode {:?}", self.context.borrow_mut().mode);
-            graphics::render(
-                &self.context,
-                &self.terminal,
-                &mut self.cursor.borrow_mut(),
-                &self.data,
-                &mut self.append_buffer,
-            );
+            // A <silent> mapping runs without showing its steps
+            if !self.context.borrow().input.silent() {
+                graphics::render(
+                    &self.context,
+                    &self.terminal,
+                    &mut self.cursor.borrow_mut(),
+                    &self.data,
+                    &mut self.append_buffer,
+                );
+            }
             let option = self.process_key_press().unwrap();
             if option == EditorHealth::Exit {
                 break;
@@ -111,19 +252,105 @@ impl Editor {
         }
     }
     pub(crate) fn open(&mut self, input_file: &str) -> std::io::Result<()> {
+        let bytes = Self::read_file(input_file)?;
+        self.load(input_file, bytes);
+        Ok(())
+    }
+    // The bytes of `input_file`, None when it isn't there. A file that isn't there is
+    // only created by the first write.
+    fn read_file(input_file: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let file = match std::fs::File::open(input_file) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut reader = BufReader::new(file);
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Some(bytes))
+    }
+    // Makes what read_file found the text of the buffer, under the name `input_file`
+    fn load(&mut self, input_file: &str, bytes: Option<Vec<u8>>) {
+        self.remove_swap();
         self.context.borrow_mut().files = input_file.to_string();
+        self.context.borrow_mut().options.reset_local();
+        self.stamp_file();
         log::debug!("{:?}", self.context.borrow().files);
         log::debug!("{:?}", input_file);
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(input_file)?;
-        let mut reader = BufReader::new(file);
-        reader.read_to_end(&mut self.data.buffer)?;
-        self.data.update_buffers();
+        let Some(bytes) = bytes else {
+            self.read_text(&[]);
+            self.set_status_message(&format!("\"{}\" [New]", input_file));
+            self.open_swap();
+            return;
+        };
+        let mut notes = self.read_text(&bytes);
+        if !files::is_writable(std::path::Path::new(input_file)) {
+            let mut c = self.context.borrow_mut();
+            c.options.set_local("readonly", OptionValue::Bool(true));
+            notes.insert_str(0, "[readonly]");
+        }
+        if !notes.is_empty() {
+            self.set_status_message(&format!("\"{}\" {}", input_file, notes));
+        }
+        self.open_swap();
         log::debug!("{:?}", self.data);
-        Ok(())
+    }
+    // yim - reads the text from stdin, it has no file name yet and counts as modified
+    pub(crate) fn open_stdin(&mut self, text: Vec<u8>) {
+        self.context.borrow_mut().files.clear();
+        self.context.borrow_mut().options.reset_local();
+        self.read_text(&text);
+        self.context.borrow_mut().dirty = 1;
+    }
+    // Takes the text of a file into the buffer, as UTF-8 and without the line ends of
+    // its 'fileformat'. An empty file goes by the global options. Returns the notes to
+    // show about the text.
+    fn read_text(&mut self, bytes: &[u8]) -> String {
+        let mut notes = String::new();
+        let mut text = bytes.to_vec();
+        let mut c = self.context.borrow_mut();
+        if !bytes.is_empty() {
+            let (encoding, bom) = files::detect_encoding(bytes);
+            let (encoding, bom) = match files::decode(bytes, encoding, bom) {
+                Some(decoded) => {
+                    match encoding {
+                        "utf-8" => (),
+                        "latin1" => notes.push_str("[not UTF-8, read as latin1]"),
+                        _ => notes.push_str("[converted]"),
+                    }
+                    text = decoded;
+                    (encoding, bom)
+                }
+                // Taken as they are the bytes are written back unchanged, a missing
+                // newline included
+                None => {
+                    notes.push_str("[CONVERSION ERROR, read as raw bytes]");
+                    c.options
+                        .set_local("fixendofline", OptionValue::Bool(false));
+                    ("utf-8", false)
+                }
+            };
+            c.options
+                .set_local("fileencoding", OptionValue::String(encoding.to_string()));
+            c.options.set_local("bomb", OptionValue::Bool(bom));
+            let format = files::detect_format(&text);
+            c.options
+                .set_local("fileformat", OptionValue::String(format.to_string()));
+        }
+        self.data.buffer = files::from_format(&text, c.options.string("fileformat"));
+        // The missing newline is put back, 'endofline' remembers it wasn't there. An
+        // empty file has none either, its empty line is written as nothing.
+        let eol = self.data.buffer.last() == Some(&b'\n');
+        c.options.set_local("endofline", OptionValue::Bool(eol));
+        if !eol {
+            self.data.buffer.push(b'\n');
+        }
+        if !eol && !bytes.is_empty() {
+            notes.push_str("[noeol]");
+        }
+        drop(c);
+        self.data.update_buffers();
+        notes
     }
     pub(crate) fn change_mode(&mut self, m: EditorModes) -> Option<EditorHealth> {
         self.context.borrow_mut().mode = m;
@@ -133,19 +360,6 @@ impl Editor {
     pub(crate) fn set_status_message(&mut self, ins: &str) {
         self.context.borrow_mut().status_message = ins.to_string();
     }
-    pub(crate) fn clear_status_message_from_editor(&mut self) {
-        let status_len: usize = self.context.borrow().status_message.capacity();
-        let mut cmd_buffer = String::new();
-        cmd_buffer.push(b':' as char);
-        for _i in 0..status_len {
-            cmd_buffer.push(' ');
-        }
-        self.terminal
-            .borrow_mut()
-            .stdout
-            .write(cmd_buffer.as_bytes())
-            .unwrap();
-    }
     pub(crate) fn file_index_to_cursor(&mut self) -> (usize, usize) {
         let mut i_x = 0;
         let mut i_y: i32 = -1;
@@ -169,13 +383,242 @@ impl Editor {
         ((i_x - value as i32) as usize, i_y as usize)
     }
     pub(crate) fn process_key_press(&mut self) -> Option<EditorHealth> {
-        let key = self.terminal.borrow_mut().read_key();
+        self.wait_for_key();
+        let key = self.next_key()?;
+        self.context.borrow_mut().input.source = key.source;
+        let health = self.handle_key(key.code);
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        self.count_swap_key();
+        health
+    }
+    // Dispatches one key: to the command it completes when one is pending, else to the
+    // handler of the current mode. Nothing in here waits for more keys.
+    pub(crate) fn handle_key(&mut self, k: u8) -> Option<EditorHealth> {
+        let pending = std::mem::take(&mut self.context.borrow_mut().pending);
         let mode = self.context.borrow().mode;
-        //let exit_key = Keys::cntrl(b'q');
-        match (key, mode) {
-            (None, _) => None,
-            (Some(k), EditorModes::Normal) => self.handle_normal_mode(k),
-            (Some(k), EditorModes::Insert) => self.handle_insert_mode(k),
+        let locked = !self.context.borrow().options.flag("modifiable");
+        let health = match (pending, mode) {
+            // Like a failing motion this ends a macro
+            _ if locked && Self::changes_text(pending, mode, k) => {
+                self.set_status_message("E21: Cannot make changes, 'modifiable' is off");
+                self.context.borrow_mut().input.abort();
+                Some(EditorHealth::Healthy)
+            }
+            (Pending::Nothing, EditorModes::Normal) => self.handle_normal_mode(k),
+            (Pending::Nothing, EditorModes::Insert) => self.handle_insert_mode(k),
+            (Pending::Nothing, EditorModes::Replace) => self.handle_replace_mode(k),
+            (Pending::Nothing, EditorModes::CommandLine) => self.handle_command_line(k),
+            (Pending::Nothing, _) => self.handle_visual_mode(k),
+            (pending, _) => self.complete_pending(pending, k),
+        };
+        // Counts and "x prefix the next command, anything that finishes one consumes them
+        let waiting = self.context.borrow().pending != Pending::Nothing;
+        let prefix = match pending {
+            Pending::Register => true,
+            // 0 is only part of a count after another digit, else it is a motion
+            Pending::Nothing => {
+                (Keys::is_number(k.into()) && self.context.borrow().line_reg != 0) || k == b'"'
+            }
+            _ => false,
+        };
+        if (mode == EditorModes::Normal || mode.is_visual()) && !waiting && !prefix {
+            self.context.borrow_mut().line_reg = 0;
+            self.context.borrow_mut().registers.selected = None;
+        }
+        // The command after Ctrl-O is done, unless it went on to visual or command line mode
+        let restart = self.context.borrow().restart_insert;
+        if restart && mode != EditorModes::Insert && !waiting && !prefix {
+            let now = self.context.borrow().mode;
+            match now {
+                EditorModes::Normal => self.resume_insert(),
+                EditorModes::Insert | EditorModes::Replace => {
+                    self.context.borrow_mut().restart_insert = false
+                }
+                _ => (),
+            }
+        }
+        health
+    }
+    // Keys starting a command that changes the text, what 'nomodifiable' refuses
+    fn changes_text(pending: Pending, mode: EditorModes, k: u8) -> bool {
+        let counts = k == Keys::cntrl(b'a') || k == Keys::cntrl(b'x');
+        match (pending, mode) {
+            (Pending::Nothing, EditorModes::Normal) => {
+                counts || b"iaIAoOdcpPJ~CSsxXD.Rr".contains(&k)
+            }
+            (Pending::Nothing, mode) if mode.is_visual() => counts || b"IAdxcs><~uUJ".contains(&k),
+            (Pending::GPrefix, EditorModes::Normal) => b"pPJ~uUr".contains(&k),
+            (Pending::GPrefix, mode) if mode.is_visual() => counts,
+            _ => false,
+        }
+    }
+    // The next key for handle_key with mappings expanded. Keys that may start a longer
+    // mapping are held back until none can match anymore or timeoutlen runs out, then
+    // the longest mapping they start with is played.
+    pub(crate) fn next_key(&mut self) -> Option<Key> {
+        loop {
+            let first = self.context.borrow_mut().input.next(&self.terminal)?;
+            let mode = match first.remap() {
+                true => self.map_mode(),
+                false => None,
+            };
+            let Some(mode) = mode else {
+                self.context.borrow_mut().input.consume(&[first]);
+                return Some(first);
+            };
+            let mut typed = vec![first];
+            loop {
+                let codes: Vec<u8> = typed.iter().map(|key| key.code).collect();
+                let waiting = {
+                    let mappings = &self.context.borrow().mappings;
+                    mappings.has_longer(mode, &codes)
+                        && !mappings.get(mode, &codes).is_some_and(|m| m.nowait)
+                };
+                if !waiting {
+                    break;
+                }
+                let timeout = {
+                    let options = &self.context.borrow().options;
+                    let timeoutlen = options.number("timeoutlen") as u64;
+                    options
+                        .flag("timeout")
+                        .then(|| Duration::from_millis(timeoutlen))
+                };
+                let mut context = self.context.borrow_mut();
+                let key = match timeout {
+                    Some(timeout) => context.input.next_within(&self.terminal, timeout),
+                    // :set notimeout waits for as long as it takes
+                    None => context.input.next(&self.terminal),
+                };
+                drop(context);
+                match key {
+                    Some(key) if key.remap() => typed.push(key),
+                    Some(key) => {
+                        self.context.borrow_mut().input.unread(&[key]);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            let codes: Vec<u8> = typed.iter().map(|key| key.code).collect();
+            let found = self
+                .context
+                .borrow()
+                .mappings
+                .longest(mode, &codes)
+                .map(|(len, mapping)| (len, mapping.clone()));
+            let mut context = self.context.borrow_mut();
+            let input = &mut context.input;
+            let Some((len, mapping)) = found else {
+                input.unread(&typed[1..]);
+                input.consume(&typed[..1]);
+                return Some(first);
+            };
+            input.unread(&typed[len..]);
+            input.consume(&typed[..len]);
+            input.depth += 1;
+            if input.depth > MAX_MAP_DEPTH {
+                input.abort();
+                context.status_message = String::from("E223: recursive mapping");
+                continue;
+            }
+            let source = |remap| KeySource::Mapping {
+                remap,
+                silent: mapping.silent,
+            };
+            // A rhs starting with its lhs doesn't expand that part again, nmap x xp works
+            let lhs = &codes[..len];
+            match !mapping.noremap && mapping.rhs.starts_with(lhs) {
+                true => {
+                    input.push_front(&mapping.rhs[len..], source(true));
+                    input.push_front(lhs, source(false));
+                }
+                false => input.push_front(&mapping.rhs, source(!mapping.noremap)),
+            }
+        }
+    }
+    // Which mappings apply to the next key, none while it completes r, m, " and such
+    fn map_mode(&self) -> Option<MapMode> {
+        let context = self.context.borrow();
+        match (context.pending, context.mode) {
+            (Pending::Nothing, EditorModes::Normal) => Some(MapMode::Normal),
+            (Pending::Nothing, EditorModes::Insert | EditorModes::Replace) => Some(MapMode::Insert),
+            (Pending::Nothing, EditorModes::CommandLine) => Some(MapMode::CommandLine),
+            (Pending::Nothing, _) => Some(MapMode::Visual),
+            (Pending::Operator(_) | Pending::CaseOperator(_), _) => Some(MapMode::OperatorPending),
+            _ => None,
+        }
+    }
+    pub(crate) fn await_key(&mut self, pending: Pending) -> Option<EditorHealth> {
+        self.context.borrow_mut().pending = pending;
+        Some(EditorHealth::Healthy)
+    }
+    // The second half of commands like dd, "a, ma or gU
+    fn complete_pending(&mut self, pending: Pending, k: u8) -> Option<EditorHealth> {
+        if pending == Pending::Listing {
+            self.context.borrow_mut().listing.clear();
+            return match k {
+                b':' => self.start_command_line(':', ""),
+                _ => Some(EditorHealth::Healthy),
+            };
+        }
+        if pending == Pending::SwapFound {
+            return self.answer_swap(k);
+        }
+        // Even Esc goes in after Ctrl-V
+        if pending == Pending::Literal {
+            return self.type_keys(&[Keys::cntrl(b'v'), k]);
+        }
+        // Esc cancels whatever was pending
+        if k == b'\x1B' {
+            return Some(EditorHealth::Healthy);
+        }
+        match pending {
+            Pending::Operator(b'd') => operations::normal::delete_motion(
+                &self.context,
+                &self.cursor,
+                &self.terminal,
+                &mut self.data,
+                &(k as char).to_string(),
+            ),
+            Pending::Operator(b'y') => operations::normal::yank_operations(
+                &self.context,
+                &self.cursor,
+                &self.terminal,
+                &self.data,
+                k,
+            ),
+            Pending::Operator(_) => self.start_change("c", &(k as char).to_string()),
+            Pending::GPrefix => self.handle_g_prefix(k),
+            Pending::CaseOperator(op) => {
+                let motion = match k == op {
+                    true => String::from("line"),
+                    false => (k as char).to_string(),
+                };
+                operations::normal::case_operations(
+                    &self.context,
+                    &self.cursor,
+                    &mut self.data,
+                    &format!("g{}", op as char),
+                    &motion,
+                )
+            }
+            Pending::ReplaceChar(signature) => operations::normal::replace_operations(
+                &self.context,
+                &self.cursor,
+                &mut self.data,
+                signature,
+                k,
+            ),
+            Pending::Register => self.select_register(k),
+            Pending::SetMark => self.set_mark(k),
+            Pending::JumpToMark(how) => self.jump_to_mark(how, k),
+            Pending::Record => self.start_recording(k),
+            Pending::PlayMacro => self.play_macro(k),
+            Pending::InsertRegister => self.insert_register(k),
+            Pending::Nothing | Pending::Listing | Pending::Literal | Pending::SwapFound => {
+                Some(EditorHealth::Healthy)
+            }
         }
     }
     pub(crate) fn update_h_reg(&mut self, k: u8) -> Option<EditorHealth> {
@@ -195,35 +638,34 @@ impl Editor {
         Some(EditorHealth::Healthy)
     }
     pub(crate) fn update_line_reg(&mut self, k: u8) -> Option<EditorHealth> {
-        let mut x = [0u8; 4];
-        let mut num: Vec<u32> = Vec::new();
-        x[3] = k;
-        num.push(u32::from_be_bytes(x) - 48);
-        //log::debug!("{:?}:{:?}", x, u32::from_be_bytes(x));
-        self.context.borrow_mut().line_reg = 0;
-        loop {
-            let key = self.terminal.borrow_mut().read_key();
-            if key.unwrap() == 13 as u8 {
-                //Until ENTER is clicked
-                break;
-            }
-            x = [0u8; 4];
-            x[3] = key.unwrap();
-            //log::debug!("{:?}:{:?}", x, u32::from_be_bytes(x));
-            num.push(u32::from_be_bytes(x) - 48);
+        let mut c = self.context.borrow_mut();
+        c.line_reg = c.line_reg * 10 + (k - b'0') as usize;
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn select_register(&mut self, k: u8) -> Option<EditorHealth> {
+        if !self.context.borrow_mut().registers.select(k as char) {
+            self.set_status_message("Invalid register name");
         }
-        let accumulator = num
-            .into_iter()
-            .rev()
-            .enumerate()
-            .fold(0, |s, (i, d)| s + (i as u32) * 10 + d);
-
-        self.context.borrow_mut().line_reg = accumulator as usize - 1;
-
-        //log::debug!("{:?}", accumulator - 1);
-
         Some(EditorHealth::Healthy)
     }
+    pub(crate) fn repeat_last_change(&mut self) -> Option<EditorHealth> {
+        let op = match self.context.borrow().last_change.clone() {
+            Some(op) => op,
+            None => return Some(EditorHealth::Healthy),
+        };
+        let count = match self.context.borrow().line_reg {
+            0 => None,
+            c => Some(c),
+        };
+        if self.context.borrow().registers.selected.is_none() {
+            self.context.borrow_mut().registers.selected = op.borrow().register;
+        }
+        let health = op.borrow().run(self, count);
+        if let Some(c) = count {
+            op.borrow_mut().count = c;
+        }
+        health
+    }
     pub(crate) fn handle_normal_mode(&mut self, k: u8) -> Option<EditorHealth> {
         match k {
             //TODO: These also move cursor
@@ -231,201 +673,1709 @@ impl Editor {
             x if Keys::is_number(x.into()) => self.update_line_reg(k),
             b'h' | b'l' | b'j' | b'k' => self.navigate(k),
             b'n' | b'N' => self.update_h_reg(k),
-            b'd' => operations::normal::delete_operations(
+            b'd' | b'y' | b'c' => self.await_key(Pending::Operator(k)),
+            // Y is yy
+            b'Y' => operations::normal::yank_operations(
                 &self.context,
                 &self.cursor,
                 &self.terminal,
+                &self.data,
+                b'y',
+            ),
+            b'p' | b'P' => operations::normal::put_operations(
+                &self.context,
+                &self.cursor,
                 &mut self.data,
-                k,
+                &(k as char).to_string(),
             ),
-            //b'g' => self.update_h_reg(k),
-            b'a' | b'I' | b'A' => self.move_cursor_insert(k),
-            b':' => self.parse_status_cmd_blocking(),
-            //b'n' => self.go_to_reg(),
-            b'/' => operations::normal::find_in_file_blocking(
+            b'g' => self.await_key(Pending::GPrefix),
+            b'a' | b'I' | b'A' | b'i' => self.move_cursor_insert(k),
+            b'o' | b'O' => self.open_line_insert(k),
+            b'J' => self.join_lines("J"),
+            b'~' => operations::normal::case_operations(
                 &self.context,
                 &self.cursor,
-                &self.terminal,
-                &self.data,
+                &mut self.data,
+                "~",
+                "~",
             ),
+            x if x == Keys::cntrl(b'a') || x == Keys::cntrl(b'x') => {
+                operations::normal::increment_operations(
+                    &self.context,
+                    &self.cursor,
+                    &mut self.data,
+                    k,
+                )
+            }
+            b'C' => self.start_change("C", "$"),
+            b'S' => self.start_change("S", "c"),
+            b's' => self.start_change("s", "l"),
+            b'x' | b'X' | b'D' => {
+                let motion = match k {
+                    b'x' => "l",
+                    b'X' => "h",
+                    _ => "$",
+                };
+                operations::normal::delete_motion(
+                    &self.context,
+                    &self.cursor,
+                    &self.terminal,
+                    &mut self.data,
+                    motion,
+                )
+            }
+            b'.' => self.repeat_last_change(),
+            b'"' => self.await_key(Pending::Register),
+            b'R' => self.enter_replace_mode(),
+            b'r' => self.await_key(Pending::ReplaceChar("r")),
+            b'v' | b'V' => self.enter_visual(k),
+            x if x == Keys::cntrl(b'v') => self.enter_visual(k),
+            b'm' => self.await_key(Pending::SetMark),
+            b'q' => self.stop_recording(),
+            b'@' => self.await_key(Pending::PlayMacro),
+            b'`' | b'\'' => self.await_key(Pending::JumpToMark(k)),
+            x if x == Keys::cntrl(b'o') || x == Keys::cntrl(b'i') => self.walk_jump_list(k),
+            b':' | b'/' => self.start_command_line(k as char, ""),
             b'\x1B' => self.change_mode(EditorModes::Normal),
-            b'i' => self.change_mode(EditorModes::Insert),
             _ => Some(EditorHealth::Healthy),
         }
     }
-    pub(crate) fn save_buffer(&mut self, file_name: &str) -> Result<(), ()> {
-        let mut f_name = self.context.borrow().files.clone();
-        if !file_name.is_empty() {
-            f_name = file_name.to_string();
+    // Commands starting with g, `key` is the one after it
+    pub(crate) fn handle_g_prefix(&mut self, key: u8) -> Option<EditorHealth> {
+        if self.context.borrow().mode.is_visual() {
+            return match key {
+                x if x == Keys::cntrl(b'a') || x == Keys::cntrl(b'x') => {
+                    operations::visual::visual_increment(
+                        &self.context,
+                        &self.cursor,
+                        &mut self.data,
+                        x,
+                        true,
+                    )
+                }
+                _ => Some(EditorHealth::Healthy),
+            };
+        }
+        match key {
+            b'p' | b'P' => operations::normal::put_operations(
+                &self.context,
+                &self.cursor,
+                &mut self.data,
+                &format!("g{}", key as char),
+            ),
+            b'v' => self.reselect_visual(),
+            b'J' => self.join_lines("gJ"),
+            b';' | b',' => self.walk_changes(key),
+            b'~' | b'u' | b'U' => self.await_key(Pending::CaseOperator(key)),
+            b'r' => self.await_key(Pending::ReplaceChar("gr")),
+            _ => Some(EditorHealth::Healthy),
         }
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(f_name)
-            .map_err(|err| {
-                self.set_status_message(format!("{} Cannot save to file", err).as_str());
-            })
-            .unwrap();
-        let mut writer = BufWriter::new(&file);
-        file.set_len(self.data.buffer.len() as u64).unwrap();
-        let buffer_len = writer.write(&self.data.buffer).unwrap();
-        self.set_status_message(format!("{} B written", buffer_len).as_str());
-        self.context.borrow_mut().dirty = 0;
-        Ok(())
-    }
-    pub(crate) fn find_in_file(&mut self, word: &str) {
-        self.context.borrow_mut().highlight_register = self.data.find(word);
     }
-    pub(crate) fn save_file(&mut self, file_name: &str) -> Result<(), ()> {
-        self.save_buffer("").unwrap();
-        self.save_buffer(file_name).unwrap();
-        Ok(())
+    fn visual_mode_for(k: u8) -> EditorModes {
+        match k {
+            b'v' => EditorModes::Visual,
+            b'V' => EditorModes::VisualLine,
+            _ => EditorModes::VisualBlock,
+        }
     }
-    pub(crate) fn exit_editor(&mut self) -> Option<EditorHealth> {
-        let _ = self.terminal.borrow_mut().stdout.write(b"\x1b[2J");
-        let _ = self.terminal.borrow_mut().stdout.write(b"\x1b[H");
-        Some(EditorHealth::Exit)
+    pub(crate) fn enter_visual(&mut self, k: u8) -> Option<EditorHealth> {
+        let anchor = (self.cursor.borrow().absx(), self.cursor.borrow().absy());
+        self.context.borrow_mut().visual_start = anchor;
+        self.context.borrow_mut().visual_to_eol = false;
+        self.change_mode(Self::visual_mode_for(k))
     }
-    pub(crate) fn run_cmd(&mut self, args: Vec<&str>) -> Option<EditorHealth> {
-        //Replace this with YIM engine later
-        let args_length = args.len();
-        if args_length <= 0 {
-            return Some(EditorHealth::Healthy);
+    // gv, brings back the last selection
+    pub(crate) fn reselect_visual(&mut self) -> Option<EditorHealth> {
+        let last = self.context.borrow().last_visual;
+        if let Some((mode, anchor, head)) = last {
+            self.context.borrow_mut().visual_start = anchor;
+            self.context.borrow_mut().visual_to_eol = false;
+            self.cursor.borrow_mut().set_x(head.0);
+            self.cursor.borrow_mut().set_y(head.1);
+            self.change_mode(mode);
         }
-        let args_args = &args[1..];
-        match args[0] {
-            "w" => {
-                self.save_file(&args_args.join(""));
-                Some(EditorHealth::Healthy)
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn handle_visual_mode(&mut self, k: u8) -> Option<EditorHealth> {
+        let mode = self.context.borrow().mode;
+        match k {
+            x if x == Keys::cntrl(b'u') || x == Keys::cntrl(b'd') => self.navigate(k),
+            x if Keys::is_number(x.into()) => self.update_line_reg(k),
+            b'h' | b'l' => {
+                self.context.borrow_mut().visual_to_eol = false;
+                self.navigate(k)
             }
-            "s" | "search" => {
-                self.find_in_file(&args_args.join(""));
+            b'j' | b'k' => self.navigate(k),
+            b'$' => {
+                self.context.borrow_mut().visual_to_eol = true;
+                self.cursor
+                    .borrow_mut()
+                    .move_cursor(&self.data.new_lines, CursorDirections::LineEnd, 1)
+                    .unwrap();
                 Some(EditorHealth::Healthy)
             }
-            "o" => {
-                self.open(&args_args.join(""));
+            b'I' | b'A' if mode == EditorModes::VisualBlock => {
+                operations::visual::start_block_insert(
+                    &self.context,
+                    &self.cursor,
+                    &mut self.data,
+                    k,
+                )
+            }
+            b'I' | b'A' => {
+                let (start, end) = operations::visual::visual_bounds(
+                    &self.context.borrow(),
+                    &self.cursor.borrow(),
+                );
+                operations::visual::leave_visual(&self.context, &self.cursor, &self.data);
+                let (x, y) = if k == b'I' { start } else { (end.0 + 1, end.1) };
+                self.cursor.borrow_mut().set_y(y);
+                self.cursor.borrow_mut().set_x(x);
+                if mode == EditorModes::VisualLine {
+                    self.position_for_insert(k);
+                }
+                let op = Operator::new(String::from("i"), operations::insert::replay_insert);
+                self.context.borrow_mut().insert_change = Some(op);
+                self.change_mode(EditorModes::Insert)
+            }
+            b'"' => self.await_key(Pending::Register),
+            x if x == Keys::cntrl(b'a') || x == Keys::cntrl(b'x') => {
+                operations::visual::visual_increment(
+                    &self.context,
+                    &self.cursor,
+                    &mut self.data,
+                    k,
+                    false,
+                )
+            }
+            b'g' => self.await_key(Pending::GPrefix),
+            b'o' => {
+                let head = (self.cursor.borrow().absx(), self.cursor.borrow().absy());
+                let anchor = std::mem::replace(&mut self.context.borrow_mut().visual_start, head);
+                self.cursor.borrow_mut().set_x(anchor.0);
+                self.cursor.borrow_mut().set_y(anchor.1);
                 Some(EditorHealth::Healthy)
             }
-            "q" => {
-                if self.context.borrow_mut().dirty != 0 {
-                    self.set_status_message(
-                        "You have unsaved changes, press :q! to quit without saving",
-                    );
+            // The key of the current kind leaves, the others switch kind
+            x if x == b'v' || x == b'V' || x == Keys::cntrl(b'v') => {
+                if Self::visual_mode_for(x) == mode {
+                    operations::visual::leave_visual(&self.context, &self.cursor, &self.data);
                     Some(EditorHealth::Healthy)
                 } else {
-                    self.exit_editor()
+                    self.change_mode(Self::visual_mode_for(x))
                 }
             }
-            "noh" => {
-                //self.clear_highlight_register();
-                self.context.borrow_mut().highlight_register.clear();
+            b'\x1B' => {
+                operations::visual::leave_visual(&self.context, &self.cursor, &self.data);
                 Some(EditorHealth::Healthy)
             }
-            "q!" => self.exit_editor(),
-            "wq" => {
-                self.save_file(&args_args.join(""));
-                self.exit_editor()
+            b':' => {
+                operations::visual::leave_visual(&self.context, &self.cursor, &self.data);
+                self.start_command_line(':', "'<,'>")
             }
-            _ => {
-                self.set_status_message("This command does not exist!!!");
-                Some(EditorHealth::Healthy)
+            b'd' | b'x' | b'y' | b'c' | b's' | b'>' | b'<' | b'~' | b'u' | b'U' | b'J' => {
+                operations::visual::visual_operations(
+                    &self.context,
+                    &self.cursor,
+                    &self.terminal,
+                    &mut self.data,
+                    k,
+                )
             }
+            _ => Some(EditorHealth::Healthy),
         }
     }
-    pub(crate) fn parse_status_cmd_blocking(&mut self) -> Option<EditorHealth> {
-        //In this mode we show user typed value.
-        //self.terminal.borrow_mut().control_echo(true);
-        // TODO: Hacky render fix alter
-        self.cursor
-            .borrow()
-            .naive_move_cursor_2d(&self.terminal, self.cursor.borrow().rows + 2, 0);
-        self.clear_status_message_from_editor();
-        let mut cmd = String::new();
-        self.cursor
-            .borrow()
-            .naive_move_cursor_2d(&self.terminal, self.cursor.borrow().rows + 2, 2);
-        // REFREFREFACTOR
-        loop {
-            let key = self.terminal.borrow_mut().read_key().unwrap();
-            if key == b'\x7F' {
-                //BACKSPACE is clicked
-                // ALL this to have backspace HAHAHA
-                cmd.pop();
-                self.cursor
-                    .borrow()
-                    .naive_move_cursor(&self.terminal, CursorDirections::Left, 1);
-                if self.terminal.borrow_mut().stdout.write(b" ").unwrap() as u32 != 1 {
-                    log::error!("Couldn't write");
-                }
-                self.cursor
-                    .borrow()
-                    .naive_move_cursor(&self.terminal, CursorDirections::Left, 1);
-                continue;
-            } else if key == 13 as u8 {
-                //Until ENTER is clicked
-                break;
-            } else if key == 27 as u8 {
-                break;
+    // Writes the buffer to `file_name`, its own file when that is empty. Writing to
+    // another file leaves the buffer modified.
+    pub(crate) fn save_buffer(&mut self, file_name: &str) -> std::io::Result<()> {
+        let own = self.context.borrow().files.clone();
+        let f_name = match file_name {
+            "" => own.clone(),
+            name => name.to_string(),
+        };
+        let path = std::path::Path::new(&f_name);
+        let new = !path.exists();
+        let (format, encoding, bom, eol, fixeol) = {
+            let options = &self.context.borrow().options;
+            (
+                options.string("fileformat").to_string(),
+                options.string("fileencoding").to_string(),
+                options.flag("bomb"),
+                options.flag("endofline"),
+                options.flag("fixendofline"),
+            )
+        };
+        // The last line goes without its newline when the file had none, unless
+        // 'fixendofline' adds it. A lone empty line is an empty file either way.
+        let mut text = &self.data.buffer[..];
+        if !eol && (!fixeol || text == b"\n") {
+            text = text.strip_suffix(b"\n").unwrap_or(text);
+        }
+        let bytes =
+            files::encode(&files::to_format(text, &format), &encoding, bom).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "E513: Write error, conversion failed (set fenc=utf-8 to override)",
+                )
+            })?;
+        files::write_file(path, &bytes, &self.context.borrow().options)?;
+        self.set_status_message(&format!(
+            "\"{}\"{}{}{}{} {}L, {}B written",
+            f_name,
+            if new { " [New]" } else { "" },
+            if encoding != "utf-8" {
+                " [converted]"
             } else {
-                if self.terminal.borrow_mut().stdout.write(&[key]).unwrap() as u32 != 1 {
-                    log::error!("Couldn't write",);
-                }
+                ""
+            },
+            match format.as_str() {
+                "unix" => String::new(),
+                format => format!(" [{}]", format),
+            },
+            if text.last() == Some(&b'\n') || text.is_empty() {
+                ""
+            } else {
+                " [noeol]"
+            },
+            match text.is_empty() {
+                true => 0,
+                false => self.data.new_lines.len(),
+            },
+            bytes.len()
+        ));
+        if f_name == own {
+            self.context.borrow_mut().dirty = 0;
+            self.stamp_file();
+            self.sync_swap();
+        }
+        Ok(())
+    }
+    // :w and :wq, false when nothing was written. A buffer without a name takes the
+    // one it is written to.
+    pub(crate) fn write_command(&mut self, file_name: &str, force: bool) -> bool {
+        let (files, readonly) = {
+            let c = self.context.borrow();
+            (c.files.clone(), c.options.flag("readonly"))
+        };
+        if file_name.is_empty() && files.is_empty() {
+            self.set_status_message("E32: No file name");
+            return false;
+        }
+        if readonly && !force && (file_name.is_empty() || file_name == files) {
+            self.set_status_message("E45: 'readonly' option is set (add ! to override)");
+            return false;
+        }
+        let own = file_name.is_empty() || file_name == files;
+        if own && !force && self.file_changed() {
+            self.set_status_message(
+                "WARNING: The file has been changed since reading it!!! (add ! to override)",
+            );
+            return false;
+        }
+        if files.is_empty() {
+            self.context.borrow_mut().files = file_name.to_string();
+        }
+        self.save_file(file_name).is_ok()
+    }
+    fn swap_wanted(&self) -> bool {
+        let c = self.context.borrow();
+        !c.files.is_empty() && c.options.flag("swapfile") && c.options.number("updatecount") > 0
+    }
+    // Before the buffer gets a swap file of its own, one left for its file by a crash
+    // or another yim is asked about
+    pub(crate) fn open_swap(&mut self) {
+        if !self.swap_wanted() {
+            return;
+        }
+        let file = self.context.borrow().files.clone();
+        match swap::find(std::path::Path::new(&file), None) {
+            Some(found) => {
+                let question = found.attention(&file);
+                self.context.borrow_mut().swap_found = Some(found);
+                self.show_listing(question);
+                self.context.borrow_mut().pending = Pending::SwapFound;
             }
-            cmd.push(key as char);
+            None => self.create_swap(),
         }
-        log::debug!("{:?} {}", cmd, cmd.len());
-        //self.terminal.borrow_mut().control_echo(false);
-        self.run_cmd(cmd.split(" ").collect())
     }
-    pub(crate) fn handle_insert_mode(&mut self, k: u8) -> Option<EditorHealth> {
-        match k {
-            b'\x1B' => self.change_mode(EditorModes::Normal),
-            b'\x7F' => {
-                self.context.borrow_mut().dirty = 1;
-                operations::insert::remove_char(&self.cursor, &mut self.data)
+    fn create_swap(&mut self) {
+        let file = self.context.borrow().files.clone();
+        match SwapFile::create(std::path::Path::new(&file), &self.data.buffer) {
+            Ok(swap) => self.context.borrow_mut().swap = Some(swap),
+            Err(err) => self.set_status_message(&format!(
+                "E303: Unable to open swap file for \"{}\", recovery impossible ({})",
+                file, err
+            )),
+        }
+    }
+    pub(crate) fn remove_swap(&mut self) {
+        if let Some(swap) = self.context.borrow_mut().swap.take() {
+            swap.remove();
+        }
+    }
+    // Copies the text into the swap file
+    pub(crate) fn sync_swap(&mut self) {
+        let mut c = self.context.borrow_mut();
+        let file = std::path::PathBuf::from(&c.files);
+        if let Some(swap) = c.swap.as_mut() {
+            if let Err(err) = swap.write(&file, &self.data.buffer) {
+                log::error!("cannot write swap file {}: {err}", swap.path.display());
             }
-            _ => {
-                self.context.borrow_mut().dirty = 1;
-                operations::insert::insert_char(&self.cursor, &mut self.data, k)
+        }
+    }
+    // Every key handled with changes around brings the next swap file update closer
+    fn count_swap_key(&mut self) {
+        let stale = {
+            let mut c = self.context.borrow_mut();
+            let dirty = c.dirty != 0;
+            match c.swap.as_mut() {
+                Some(swap) if dirty => {
+                    swap.stale += 1;
+                    swap.stale
+                }
+                _ => 0,
             }
+        };
+        if stale > 0 && stale >= self.context.borrow().options.number("updatecount") {
+            self.sync_swap();
         }
     }
-    pub(crate) fn move_cursor_insert(&mut self, k: u8) -> Option<EditorHealth> {
-        match k {
-            b'I' => {
-                self.cursor
-                    .borrow_mut()
-                    .move_cursor(&self.data.new_lines, CursorDirections::LineBegin, 1)
-                    .unwrap();
-                self.change_mode(EditorModes::Insert);
+    // Changes not in the swap file yet go in once no key comes for 'updatetime'
+    fn wait_for_key(&mut self) {
+        let (stale, idle) = {
+            let c = self.context.borrow();
+            let stale = c.swap.as_ref().is_some_and(|swap| swap.stale > 0);
+            (
+                stale && c.input.is_empty(),
+                c.options.number("updatetime") as u64,
+            )
+        };
+        if stale
+            && !self
+                .terminal
+                .borrow_mut()
+                .key_within(Duration::from_millis(idle))
+        {
+            self.sync_swap();
+        }
+    }
+    // [O]pen Read-Only, (E)dit anyway, (R)ecover, (D)elete it, (Q)uit
+    pub(crate) fn answer_swap(&mut self, k: u8) -> Option<EditorHealth> {
+        // yim -r answers without a key press, the question must not wait for one after
+        self.context.borrow_mut().pending = Pending::Nothing;
+        let Some(found) = self.context.borrow_mut().swap_found.take() else {
+            return Some(EditorHealth::Healthy);
+        };
+        match k.to_ascii_lowercase() {
+            b'o' | b'\r' => {
+                let mut c = self.context.borrow_mut();
+                c.options.set_local("readonly", OptionValue::Bool(true));
             }
-            b'A' => {
-                self.cursor
-                    .borrow_mut()
-                    .move_cursor(&self.data.new_lines, CursorDirections::LineEnd, 1)
-                    .unwrap();
-                self.change_mode(EditorModes::Insert);
+            b'e' => self.create_swap(),
+            b'r' => {
+                self.recover_from(found);
+                self.create_swap();
             }
-            b'a' => {
-                self.cursor
-                    .borrow_mut()
-                    .move_cursor(&self.data.new_lines, CursorDirections::Right, 1)
-                    .unwrap();
-                self.change_mode(EditorModes::Insert);
+            b'd' if !found.running() => {
+                std::fs::remove_file(&found.path).ok();
+                self.create_swap();
+            }
+            b'q' | b'a' => return self.exit_editor(),
+            // Anything else asks again
+            _ => {
+                self.context.borrow_mut().swap_found = Some(found);
+                self.context.borrow_mut().pending = Pending::SwapFound;
+                return Some(EditorHealth::Healthy);
             }
-            _ => unreachable!(),
         }
+        self.context.borrow_mut().listing.clear();
         Some(EditorHealth::Healthy)
     }
-    pub(crate) fn navigate(&mut self, k: u8) -> Option<EditorHealth> {
-        // TODO: Make here better A lot of repetittions
-        log::debug!("{:?}", self.cursor.borrow());
-        match k {
-            b'h' => self
-                .cursor
-                .borrow_mut()
-                .move_cursor(&self.data.new_lines, CursorDirections::Left, 1)
+    // :recover and yim -r, the text of the swap file left for this file
+    pub(crate) fn recover(&mut self) {
+        let file = self.context.borrow().files.clone();
+        if self.context.borrow().pending == Pending::SwapFound {
+            self.answer_swap(b'r');
+            return;
+        }
+        let own = self
+            .context
+            .borrow()
+            .swap
+            .as_ref()
+            .map(|swap| swap.path.clone());
+        match swap::find(std::path::Path::new(&file), own.as_deref()) {
+            Some(found) => self.recover_from(found),
+            None => self.set_status_message(&format!("E305: No swap file found for {}", file)),
+        }
+    }
+    fn recover_from(&mut self, found: FoundSwap) {
+        let name = found
+            .path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        self.data.buffer = found.text;
+        self.data.update_buffers();
+        self.data.edits.clear();
+        self.cursor.borrow_mut().set_y(0);
+        self.cursor.borrow_mut().set_x(0);
+        self.context.borrow_mut().dirty = 1;
+        self.set_status_message(&format!(
+            "Recovered from {}, check the text and :w it, then delete {}",
+            name, name
+        ));
+    }
+    // Remembers the buffer's file as it is now on disk
+    pub(crate) fn stamp_file(&mut self) {
+        let stamp = FileStamp::of(std::path::Path::new(&self.context.borrow().files));
+        let mut c = self.context.borrow_mut();
+        c.file_stamp = stamp;
+        c.stamp_warned = false;
+    }
+    // Something else wrote or removed the buffer's file since it was read or written
+    pub(crate) fn file_changed(&self) -> bool {
+        let c = self.context.borrow();
+        !c.files.is_empty() && FileStamp::of(std::path::Path::new(&c.files)) != c.file_stamp
+    }
+    // Tells about the file changing under the buffer once, or loads it again when
+    // 'autoread' is on and there is nothing to lose
+    pub(crate) fn check_file(&mut self) {
+        if self.context.borrow().stamp_warned || !self.file_changed() {
+            return;
+        }
+        self.context.borrow_mut().stamp_warned = true;
+        let (file, dirty, autoread) = {
+            let c = self.context.borrow();
+            (c.files.clone(), c.dirty, c.options.flag("autoread"))
+        };
+        if !std::path::Path::new(&file).exists() {
+            self.set_status_message(&format!("E211: File \"{}\" no longer available", file));
+        } else if autoread && dirty == 0 {
+            self.reload_file();
+        } else {
+            self.set_status_message(&format!(
+                "W11: Warning: File \"{}\" has changed since editing started (:e! to load it)",
+                file
+            ));
+        }
+    }
+    // :e! and 'autoread', the file read again with the cursor kept on its line
+    pub(crate) fn reload_file(&mut self) {
+        let file = self.context.borrow().files.clone();
+        let line = self.cursor.borrow().absy();
+        if let Err(err) = self.switch_file(&file) {
+            self.set_status_message(&format!("Can't open {}: {}", file, err));
+            return;
+        }
+        let line = line.min(self.data.new_lines.len().saturating_sub(1));
+        self.cursor.borrow_mut().set_y(line);
+        self.set_status_message(&format!(
+            "\"{}\" {}L, {}B",
+            file,
+            self.data.new_lines.len(),
+            self.data.buffer.len()
+        ));
+    }
+    // :e[!] [file], the current file again when none is given
+    pub(crate) fn edit_command(&mut self, file: &str, force: bool) {
+        let (files, dirty) = {
+            let c = self.context.borrow();
+            (c.files.clone(), c.dirty)
+        };
+        if dirty != 0 && !force {
+            self.set_status_message("E37: No write since last change (add ! to override)");
+            return;
+        }
+        match file {
+            "" if files.is_empty() => self.set_status_message("E32: No file name"),
+            "" => self.reload_file(),
+            file if file == files => self.reload_file(),
+            file => {
+                if let Err(err) = self.switch_file(file) {
+                    self.set_status_message(&format!("Can't open {}: {}", file, err));
+                }
+            }
+        }
+    }
+    pub(crate) fn find_in_file(&mut self, word: &str) {
+        self.context.borrow_mut().highlight_register = self.data.find(word);
+    }
+    // +/pat goes to the first line with a match. A /pat/ address looks below the
+    // cursor first, so the search starts from the last line and wraps around to take
+    // the first line too.
+    pub(crate) fn search_from_top(&mut self, command: &str) -> Option<EditorHealth> {
+        let start = self.cursor.borrow().absy();
+        let last = self.data.new_lines.len().saturating_sub(1);
+        self.cursor.borrow_mut().set_y(last);
+        let (range, rest) = self.parse_range(command);
+        self.cursor.borrow_mut().set_y(start);
+        match range {
+            Some((_, line)) if rest.trim().is_empty() => {
+                self.execute_command(&(line + 1).to_string())
+            }
+            _ => self.execute_command(command),
+        }
+    }
+    pub(crate) fn save_file(&mut self, file_name: &str) -> Result<(), ()> {
+        self.save_buffer(file_name).map_err(|err| {
+            let f_name = match file_name {
+                "" => self.context.borrow().files.clone(),
+                name => name.to_string(),
+            };
+            self.set_status_message(&format!("\"{}\" {}", f_name, err));
+        })
+    }
+    pub(crate) fn exit_editor(&mut self) -> Option<EditorHealth> {
+        self.remove_swap();
+        let _ = self.terminal.borrow_mut().stdout.write(b"\x1b[2J");
+        let _ = self.terminal.borrow_mut().stdout.write(b"\x1b[H");
+        Some(EditorHealth::Exit)
+    }
+    // Commands taking a line range, the current line when none was given
+    pub(crate) fn run_range_cmd(
+        &mut self,
+        range: Option<(usize, usize)>,
+        args: Vec<&str>,
+    ) -> Option<EditorHealth> {
+        let current = self.cursor.borrow().absy();
+        let (first, last) = range.unwrap_or((current, current));
+        let lines = last - first + 1;
+        let yank = matches!(args[0], "y" | "yank");
+        if !yank && !self.context.borrow().options.flag("modifiable") {
+            self.set_status_message("E21: Cannot make changes, 'modifiable' is off");
+            return Some(EditorHealth::Healthy);
+        }
+        if let Some(name) = args.get(1).and_then(|a| a.chars().next()) {
+            self.context.borrow_mut().registers.select(name);
+        }
+        self.cursor.borrow_mut().set_y(first);
+        self.cursor.borrow_mut().set_x(0);
+        match args[0] {
+            "d" | "delete" => {
+                operations::normal::apply_delete(
+                    &self.context,
+                    &self.cursor,
+                    &self.terminal,
+                    &mut self.data,
+                    "d",
+                    lines,
+                );
+            }
+            "y" | "yank" => {
+                operations::normal::apply_yank(
+                    &self.context,
+                    &self.cursor,
+                    &self.terminal,
+                    &self.data,
+                    "y",
+                    lines,
+                );
+                self.cursor.borrow_mut().set_y(current);
+            }
+            "j" | "join" => {
+                operations::normal::join_lines(
+                    &self.context,
+                    &self.cursor,
+                    &mut self.data,
+                    first,
+                    lines,
+                    true,
+                );
+            }
+            shift => operations::normal::shift_lines(
+                &self.context,
+                &self.cursor,
+                &mut self.data,
+                first,
+                last,
+                shift.starts_with('>'),
+                shift.len(),
+            ),
+        }
+        self.context.borrow_mut().registers.selected = None;
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn run_cmd(
+        &mut self,
+        range: Option<(usize, usize)>,
+        args: Vec<&str>,
+    ) -> Option<EditorHealth> {
+        //Replace this with YIM engine later
+        let args_length = args.len();
+        if args_length == 0 {
+            return Some(EditorHealth::Healthy);
+        }
+        let args_args = &args[1..];
+        match (args[0], range) {
+            ("", Some((_, last))) => {
+                // :N goes to line N
+                let last = last.min(self.data.new_lines.len().saturating_sub(1));
+                self.remember_jump();
+                self.cursor.borrow_mut().set_y(last);
+                self.cursor.borrow_mut().set_x(0);
+                return Some(EditorHealth::Healthy);
+            }
+            ("d" | "delete" | "y" | "yank" | "j" | "join", _) => {
+                return self.run_range_cmd(range, args)
+            }
+            (shift, _) if shift.starts_with(['>', '<']) => return self.run_range_cmd(range, args),
+            (_, Some(_)) => {
+                self.set_status_message("No range allowed");
+                return Some(EditorHealth::Healthy);
+            }
+            _ => (),
+        }
+        match args[0] {
+            "w" | "w!" => {
+                self.write_command(&args_args.join(""), args[0].ends_with('!'));
+                Some(EditorHealth::Healthy)
+            }
+            "s" | "search" => {
+                self.find_in_file(&args_args.join(""));
+                Some(EditorHealth::Healthy)
+            }
+            "q" => {
+                let left = {
+                    let c = self.context.borrow();
+                    c.arglist.len().saturating_sub(c.arg_index + 1)
+                };
+                if self.context.borrow_mut().dirty != 0 {
+                    self.set_status_message(
+                        "You have unsaved changes, press :q! to quit without saving",
+                    );
+                    Some(EditorHealth::Healthy)
+                } else if left > 0 {
+                    self.set_status_message(&format!("E173: {} more files to edit", left));
+                    Some(EditorHealth::Healthy)
+                } else {
+                    self.exit_editor()
+                }
+            }
+            "n" | "next" | "n!" | "next!" => {
+                let index = self.context.borrow().arg_index + 1;
+                let count = self.context.borrow().arglist.len();
+                match index < count {
+                    true => self.edit_arg(index, args[0].ends_with('!')),
+                    false => self.set_status_message("E165: Cannot go beyond last file"),
+                }
+                Some(EditorHealth::Healthy)
+            }
+            "N" | "Next" | "prev" | "previous" | "N!" | "Next!" | "prev!" | "previous!" => {
+                let index = self.context.borrow().arg_index.checked_sub(1);
+                match index {
+                    Some(index) => self.edit_arg(index, args[0].ends_with('!')),
+                    None => self.set_status_message("E164: Cannot go before first file"),
+                }
+                Some(EditorHealth::Healthy)
+            }
+            "fir" | "first" | "rew" | "rewind" | "fir!" | "first!" | "rew!" | "rewind!" => {
+                let count = self.context.borrow().arglist.len();
+                if count > 0 {
+                    self.edit_arg(0, args[0].ends_with('!'));
+                }
+                Some(EditorHealth::Healthy)
+            }
+            "la" | "last" | "la!" | "last!" => {
+                let count = self.context.borrow().arglist.len();
+                if let Some(index) = count.checked_sub(1) {
+                    self.edit_arg(index, args[0].ends_with('!'));
+                }
+                Some(EditorHealth::Healthy)
+            }
+            "ar" | "args" => {
+                let c = self.context.borrow();
+                let args: Vec<String> = c
+                    .arglist
+                    .iter()
+                    .enumerate()
+                    .map(|(i, file)| match i == c.arg_index {
+                        true => format!("[{}]", file),
+                        false => file.clone(),
+                    })
+                    .collect();
+                drop(c);
+                self.set_status_message(&args.join(" "));
+                Some(EditorHealth::Healthy)
+            }
+            // :o is the old name of :e
+            "e" | "edit" | "e!" | "edit!" | "o" | "o!" => {
+                self.edit_command(&args_args.join(" "), args[0].ends_with('!'));
+                Some(EditorHealth::Healthy)
+            }
+            "rec" | "recover" => {
+                self.recover();
+                Some(EditorHealth::Healthy)
+            }
+            "checkt" | "checktime" => {
+                self.context.borrow_mut().stamp_warned = false;
+                self.check_file();
+                Some(EditorHealth::Healthy)
+            }
+            "ju" | "jumps" => {
+                self.list_jumps();
+                Some(EditorHealth::Healthy)
+            }
+            "changes" => {
+                self.list_changes();
+                Some(EditorHealth::Healthy)
+            }
+            "reg" | "registers" | "di" | "display" => {
+                self.list_registers(&args_args.join(""));
+                Some(EditorHealth::Healthy)
+            }
+            "so" | "source" => {
+                let path = args_args.join(" ");
+                if self.source(&path).is_err() {
+                    self.set_status_message(&format!("E484: Can't open file {}", path));
+                }
+                Some(EditorHealth::Healthy)
+            }
+            "let" => {
+                self.let_variable(&args_args.join(" "));
+                Some(EditorHealth::Healthy)
+            }
+            "noh" => {
+                //self.clear_highlight_register();
+                self.context.borrow_mut().highlight_register.clear();
+                Some(EditorHealth::Healthy)
+            }
+            "q!" => self.exit_editor(),
+            "wq" | "wq!" => match self.write_command(&args_args.join(""), args[0].ends_with('!')) {
+                true => self.exit_editor(),
+                false => Some(EditorHealth::Healthy),
+            },
+            _ => {
+                self.set_status_message("This command does not exist!!!");
+                Some(EditorHealth::Healthy)
+            }
+        }
+    }
+    // Only the registers named in `names` when given, like `:reg a1`
+    pub(crate) fn list_registers(&mut self, names: &str) {
+        let width = self.cursor.borrow().cols.saturating_sub(10);
+        let mut lines = vec![String::from("Type Name Content")];
+        let c = self.context.borrow();
+        for (name, register) in c.registers.list(&c.files) {
+            if !names.is_empty() && !names.contains(name) {
+                continue;
+            }
+            let kind = match register.kind {
+                RegisterKind::Charwise => 'c',
+                RegisterKind::Linewise => 'l',
+                RegisterKind::Blockwise => 'b',
+            };
+            lines.push(format!(
+                "  {}  \"{}   {}",
+                kind,
+                name,
+                register.preview(width)
+            ));
+        }
+        drop(c);
+        self.show_listing(lines);
+    }
+    pub(crate) fn list_jumps(&mut self) {
+        let c = self.context.borrow();
+        let (jumps, index) = c.marks.jumps();
+        let entries = jumps.iter().map(|j| (j.file.clone(), j.offset)).collect();
+        drop(c);
+        self.list_positions(" jump line  col file/text", entries, index);
+    }
+    pub(crate) fn list_changes(&mut self) {
+        let c = self.context.borrow();
+        let (changes, index) = c.marks.changes();
+        let entries = changes
+            .iter()
+            .map(|offset| (c.files.clone(), *offset))
+            .collect();
+        drop(c);
+        self.list_positions("change line  col text", entries, index);
+    }
+    // :jumps and :changes, entries are numbered by their distance from `index` which
+    // gets the > marker
+    fn list_positions(&mut self, header: &str, entries: Vec<(String, usize)>, index: usize) {
+        let width = self.cursor.borrow().cols.saturating_sub(20);
+        let file = self.context.borrow().files.clone();
+        let mut lines = vec![header.to_string()];
+        for (i, (entry_file, offset)) in entries.iter().enumerate() {
+            let line = line_of(&self.data.new_lines, *offset);
+            let (mut col, mut text) = (0, entry_file.clone());
+            if *entry_file == file {
+                let (line_begin, line_end) = self
+                    .cursor
+                    .borrow()
+                    .calculate_row_of_insert_indices(line, &self.data.new_lines);
+                col = offset.saturating_sub(line_begin);
+                let content = self.data.buffer[line_begin..line_end].to_vec();
+                let register = Register::new(RegisterKind::Charwise, content);
+                text = register.preview(width).trim_start().to_string();
+            }
+            let marker = if i == index { '>' } else { ' ' };
+            lines.push(format!(
+                "{}{:>5} {:>5} {:>4} {}",
+                marker,
+                i.abs_diff(index),
+                line + 1,
+                col,
+                text
+            ));
+        }
+        if index == entries.len() {
+            lines.push(String::from(">"));
+        }
+        self.show_listing(lines);
+    }
+    // Full screen output of commands like :registers, stays until a key is pressed
+    pub(crate) fn show_listing(&mut self, lines: Vec<String>) {
+        self.context.borrow_mut().listing = lines;
+        self.context.borrow_mut().pending = Pending::Listing;
+    }
+    // One line address of a range: a number, `.`, `$`, or `'<`/`'>` for the last selection
+    fn parse_address<'a>(&self, cmd: &'a str) -> Option<(usize, &'a str)> {
+        let current = self.cursor.borrow().absy();
+        let last_line = self.data.new_lines.len().saturating_sub(1);
+        let digits = cmd.chars().take_while(|c| c.is_ascii_digit()).count();
+        if digits > 0 {
+            let n: usize = cmd[..digits].parse().ok()?;
+            return Some((n.saturating_sub(1), &cmd[digits..]));
+        }
+        if let Some(rest) = cmd.strip_prefix('.') {
+            return Some((current, rest));
+        }
+        if let Some(rest) = cmd.strip_prefix('$') {
+            return Some((last_line, rest));
+        }
+        // /pat/ is the next line with a match and ?pat? the previous one, both wrap
+        // around the file. An empty pattern is the last search.
+        if let Some(kind) = cmd.chars().next().filter(|c| *c == '/' || *c == '?') {
+            let body = &cmd[1..];
+            let end = body.find(kind).unwrap_or(body.len());
+            let rest = body.get(end + 1..).unwrap_or("");
+            let pattern = match &body[..end] {
+                "" => self.context.borrow().registers.last_search.clone(),
+                pattern => pattern.to_string(),
+            };
+            if pattern.is_empty() {
+                return None;
+            }
+            let matches = self.data.find(&pattern);
+            let lines = matches
+                .iter()
+                .map(|(start, _)| line_of(&self.data.new_lines, *start));
+            let line = match kind {
+                '/' => lines.clone().find(|l| *l > current).or(lines.min()),
+                _ => lines.clone().filter(|l| *l < current).max().or(lines.max()),
+            };
+            return line.map(|line| (line, rest));
+        }
+        // 'x is the line of mark x, '< and '> of the last selection
+        let rest = cmd.strip_prefix('\'')?;
+        let name = rest.chars().next()?;
+        let c = self.context.borrow();
+        let position = c.marks.get(name, &c.files).filter(|p| p.file == c.files)?;
+        Some((
+            line_of(&self.data.new_lines, position.offset),
+            &rest[name.len_utf8()..],
+        ))
+    }
+    // Line range in front of an ex command, `%` being the whole file. Lines are 0 based
+    // and inclusive, the rest of the command comes back alongside.
+    pub(crate) fn parse_range<'a>(&self, cmd: &'a str) -> (Option<(usize, usize)>, &'a str) {
+        if let Some(rest) = cmd.strip_prefix('%') {
+            let last_line = self.data.new_lines.len().saturating_sub(1);
+            return (Some((0, last_line)), rest);
+        }
+        let (first, rest) = match self.parse_address(cmd) {
+            Some(a) => a,
+            None => return (None, cmd),
+        };
+        let (last, rest) = match rest.strip_prefix(',').and_then(|r| self.parse_address(r)) {
+            Some(a) => a,
+            None => (first, rest),
+        };
+        (Some((first.min(last), first.max(last))), rest)
+    }
+    pub(crate) fn execute_command(&mut self, cmd: &str) -> Option<EditorHealth> {
+        let (range, rest) = self.parse_range(cmd.trim_start());
+        let rest = rest.trim();
+        // A pattern address that matched nothing
+        if range.is_none() && rest.starts_with(['/', '?']) {
+            let pattern = rest[1..].split(&rest[..1]).next().unwrap_or("");
+            self.set_status_message(&format!("E486: Pattern not found: {}", pattern));
+            return Some(EditorHealth::Healthy);
+        }
+        // `:>>` and `:<` don't need a space before their argument
+        let shifts = rest.chars().take_while(|c| *c == '>' || *c == '<').count();
+        let (name, args) = match shifts {
+            0 => rest.split_once(' ').unwrap_or((rest, "")),
+            n => (&rest[..n], &rest[n..]),
+        };
+        // Mappings take the rest of the line as it is, spaces included
+        if let Some((command, modes)) = Mappings::parse_command(name) {
+            return self.map_command(command, &modes, args);
+        }
+        let set_scope = match name {
+            "se" | "set" => Some(SetScope::Both),
+            "setl" | "setlocal" => Some(SetScope::Local),
+            "setg" | "setglobal" => Some(SetScope::Global),
+            _ => None,
+        };
+        if let Some(scope) = set_scope {
+            return self.set_options(scope, args);
+        }
+        let mut args: Vec<&str> = args.split(' ').filter(|a| !a.is_empty()).collect();
+        args.insert(0, name);
+        self.run_cmd(range, args)
+    }
+    // :map {lhs} {rhs}, :unmap {lhs} and :mapclear for the given modes. :map alone or
+    // with just a lhs lists what is mapped.
+    pub(crate) fn map_command(
+        &mut self,
+        command: MapCommand,
+        modes: &[MapMode],
+        args: &str,
+    ) -> Option<EditorHealth> {
+        let mut args = args.trim_start();
+        let mut mapping = Mapping {
+            rhs: Vec::new(),
+            noremap: command == MapCommand::Map { noremap: true },
+            silent: false,
+            nowait: false,
+        };
+        let mut unique = false;
+        loop {
+            let (flag, rest) = match args.split_once('>') {
+                Some((flag, rest)) if flag.starts_with('<') => (flag, rest),
+                _ => break,
+            };
+            match flag.to_ascii_lowercase().as_str() {
+                "<silent" => mapping.silent = true,
+                "<nowait" => mapping.nowait = true,
+                "<unique" => unique = true,
+                // One buffer at a time, a buffer local mapping is just a mapping
+                "<buffer" => (),
+                _ => break,
+            }
+            args = rest.trim_start();
+        }
+        let leader = self.context.borrow().mappings.leader.clone();
+        let (lhs, rhs) = match args.split_once([' ', '\t']) {
+            Some((lhs, rhs)) => (lhs, rhs.trim_start()),
+            None => (args, ""),
+        };
+        let lhs = parse_keys(lhs, &leader);
+        let abbreviation = modes == [MapMode::Abbreviation];
+        match command {
+            MapCommand::Clear => {
+                for mode in modes {
+                    self.context.borrow_mut().mappings.clear(*mode);
+                }
+            }
+            MapCommand::Unmap => {
+                let mut removed = false;
+                for mode in modes {
+                    removed |= self
+                        .context
+                        .borrow_mut()
+                        .mappings
+                        .remove(*mode, &lhs)
+                        .is_some();
+                }
+                match (removed, abbreviation) {
+                    (true, _) => (),
+                    (false, true) => self.set_status_message("E24: No such abbreviation"),
+                    (false, false) => self.set_status_message("E31: No such mapping"),
+                }
+            }
+            MapCommand::Map { .. } if rhs.is_empty() => {
+                let lines = self.context.borrow().mappings.listing(modes, &lhs);
+                match (lines.is_empty(), abbreviation) {
+                    (true, true) => self.set_status_message("No abbreviation found"),
+                    (true, false) => self.set_status_message("No mapping found"),
+                    (false, _) => self.show_listing(lines),
+                }
+            }
+            // Only whole words are expanded
+            MapCommand::Map { .. }
+                if abbreviation
+                    && !lhs
+                        .iter()
+                        .all(|c| self.context.borrow().options.is_keyword(*c)) =>
+            {
+                self.set_status_message("E474: Invalid argument");
+            }
+            MapCommand::Map { .. } => {
+                let exists = modes
+                    .iter()
+                    .any(|mode| self.context.borrow().mappings.get(*mode, &lhs).is_some());
+                if unique && exists {
+                    let message =
+                        format!("E227: Mapping already exists for {}", key_notation(&lhs));
+                    self.set_status_message(&message);
+                    return Some(EditorHealth::Healthy);
+                }
+                mapping.rhs = parse_keys(rhs, &leader);
+                for mode in modes {
+                    self.context
+                        .borrow_mut()
+                        .mappings
+                        .insert(*mode, &lhs, mapping.clone());
+                }
+            }
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // :set with any number of arguments, :set alone lists what differs from the
+    // defaults and :set all every option
+    pub(crate) fn set_options(&mut self, scope: SetScope, args: &str) -> Option<EditorHealth> {
+        let args = split_set_args(args);
+        let file_options = |options: &Options| {
+            ["fileformat", "fileencoding", "bomb"].map(|name| options.get(name).clone())
+        };
+        let before = file_options(&self.context.borrow().options);
+        let mut shown = Vec::new();
+        let mut error = None;
+        match args.first().map(String::as_str) {
+            None | Some("all") => {
+                let all = !args.is_empty();
+                shown.push(String::from("--- Options ---"));
+                shown.extend(self.context.borrow().options.listing(all, scope));
+            }
+            Some(_) => {
+                for arg in args.iter() {
+                    let result = self.context.borrow_mut().options.set(arg, scope);
+                    match result {
+                        Ok(Some(line)) => shown.push(line),
+                        Ok(None) => (),
+                        Err(err) => {
+                            error = Some(err);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.apply_options();
+        // Other line ends or another encoding are a change to the file
+        if file_options(&self.context.borrow().options) != before {
+            self.context.borrow_mut().dirty = 1;
+        }
+        match (error, shown.len()) {
+            (Some(err), _) => self.set_status_message(&err),
+            (None, 0) => (),
+            (None, 1) => self.set_status_message(&shown[0]),
+            (None, _) => self.show_listing(shown),
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // :let mapleader = ",", the only variable yim has
+    pub(crate) fn let_variable(&mut self, assignment: &str) {
+        let (name, value) = assignment.split_once('=').unwrap_or((assignment, ""));
+        let value = value.trim().trim_matches(['"', '\'']);
+        match name.trim() {
+            "mapleader" | "g:mapleader" => {
+                let leader = parse_keys(value, b"");
+                self.context.borrow_mut().mappings.leader = leader;
+            }
+            name => self.set_status_message(&format!("E121: Undefined variable: {}", name)),
+        }
+    }
+    // : and / type on the bottom line, Enter runs the command or keeps the search
+    pub(crate) fn start_command_line(&mut self, kind: char, prefill: &str) -> Option<EditorHealth> {
+        self.context.borrow_mut().command_line = (kind, prefill.to_string());
+        self.change_mode(EditorModes::CommandLine)
+    }
+    pub(crate) fn handle_command_line(&mut self, k: u8) -> Option<EditorHealth> {
+        let kind = self.context.borrow().command_line.0;
+        match k {
+            b'\x1B' => {
+                if kind == '/' {
+                    self.context.borrow_mut().highlight_register.clear();
+                }
+                return self.change_mode(EditorModes::Normal);
+            }
+            b'\r' => {
+                let text = std::mem::take(&mut self.context.borrow_mut().command_line.1);
+                self.change_mode(EditorModes::Normal);
+                if text.is_empty() {
+                    return Some(EditorHealth::Healthy);
+                }
+                if kind == '/' {
+                    self.context.borrow_mut().registers.last_search = text;
+                    return Some(EditorHealth::Healthy);
+                }
+                self.context.borrow_mut().registers.last_command = text.clone();
+                return self.execute_command(&text);
+            }
+            // Backspace on an empty line leaves it, like vim
+            b'\x7F' => {
+                if self.context.borrow_mut().command_line.1.pop().is_none() {
+                    return self.change_mode(EditorModes::Normal);
+                }
+            }
+            _ => self.context.borrow_mut().command_line.1.push(k as char),
+        }
+        // Searching highlights the matches as the word is typed
+        if kind == '/' {
+            let word = self.context.borrow().command_line.1.clone();
+            self.context.borrow_mut().highlight_register = self.data.find(&word);
+        }
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn handle_insert_mode(&mut self, k: u8) -> Option<EditorHealth> {
+        match k {
+            x if x == Keys::cntrl(b'v') => return self.await_key(Pending::Literal),
+            x if x == Keys::cntrl(b'r') => return self.await_key(Pending::InsertRegister),
+            x if x == Keys::cntrl(b'o') => return self.insert_one_command(),
+            _ => (),
+        }
+        if self.expand_abbreviation(k) {
+            return Some(EditorHealth::Healthy);
+        }
+        self.insert_key(k)
+    }
+    // A non-keyword key typed right after an abbreviation replaces it. The word is
+    // backspaced over and the expansion typed, followed by `k` again; keys typed by a
+    // mapping like those never expand, so neither does that `k`.
+    pub(crate) fn expand_abbreviation(&mut self, k: u8) -> bool {
+        // Editing keys like Ctrl-W don't expand, Enter, Tab and Esc do
+        let is_keyword = |c: u8| self.context.borrow().options.is_keyword(c);
+        if is_keyword(k) || (Keys::is_cntrl(k.into()) && !b"\r\t\x1B".contains(&k)) {
+            return false;
+        }
+        if matches!(
+            self.context.borrow().input.source,
+            KeySource::Mapping { .. }
+        ) {
+            return false;
+        }
+        let offset = operations::normal::cursor_offset(&self.cursor, &self.data);
+        let y = self.cursor.borrow().absy();
+        let (line_begin, _) = self
+            .cursor
+            .borrow()
+            .calculate_row_of_insert_indices(y, &self.data.new_lines);
+        let before = &self.data.buffer[line_begin.min(offset)..offset];
+        let word_len = before.iter().rev().take_while(|c| is_keyword(**c)).count();
+        let word = &before[before.len() - word_len..];
+        let abbreviation = match word.is_empty() {
+            true => None,
+            false => self
+                .context
+                .borrow()
+                .mappings
+                .get(MapMode::Abbreviation, word)
+                .cloned(),
+        };
+        let Some(abbreviation) = abbreviation else {
+            return false;
+        };
+        let mut expansion = abbreviation.rhs;
+        expansion.push(k);
+        let mut context = self.context.borrow_mut();
+        let remap = !abbreviation.noremap;
+        let source = |remap| KeySource::Mapping {
+            remap,
+            silent: false,
+        };
+        context.input.push_front(&expansion, source(remap));
+        context
+            .input
+            .push_front(&vec![b'\x7F'; word_len], source(false));
+        true
+    }
+    pub(crate) fn insert_key(&mut self, k: u8) -> Option<EditorHealth> {
+        if k == b'\x1B' {
+            self.finish_insert_change();
+            self.mark_here('^');
+            return self.change_mode(EditorModes::Normal);
+        }
+        // Other control characters only go in quoted with Ctrl-V
+        if Keys::is_cntrl(k.into()) && !b"\r\t\x7F\x08\x17\x15\x14\x04".contains(&k) {
+            return Some(EditorHealth::Healthy);
+        }
+        self.type_keys(&[k])
+    }
+    // Keys typed into the insert, also kept for `.`
+    pub(crate) fn type_keys(&mut self, keys: &[u8]) -> Option<EditorHealth> {
+        if let Some(op) = self.context.borrow().insert_change.as_ref() {
+            op.borrow_mut().inserted.extend(keys);
+        }
+        self.context.borrow_mut().dirty = 1;
+        operations::insert::type_keys(&self.context, &self.cursor, &mut self.data, keys);
+        Some(EditorHealth::Healthy)
+    }
+    // Ctrl-R{reg} types the register as if its text was typed, control characters
+    // in it quoted so they go in as they are
+    pub(crate) fn insert_register(&mut self, k: u8) -> Option<EditorHealth> {
+        if !Registers::is_valid(k as char) {
+            return Some(EditorHealth::Healthy);
+        }
+        let register = {
+            let c = self.context.borrow();
+            c.registers.get(Some(k as char), &c.files)
+        };
+        let Some(register) = register else {
+            return Some(EditorHealth::Healthy);
+        };
+        let mut keys = Vec::new();
+        for c in register.text {
+            match c {
+                b'\n' => keys.push(b'\r'),
+                b'\t' => keys.push(c),
+                c if Keys::is_cntrl(c.into()) => keys.extend([Keys::cntrl(b'v'), c]),
+                c => keys.push(c),
+            }
+        }
+        let source = KeySource::Mapping {
+            remap: false,
+            silent: false,
+        };
+        self.context.borrow_mut().input.push_front(&keys, source);
+        Some(EditorHealth::Healthy)
+    }
+    // Ctrl-O: one normal mode command, then handle_key comes back with resume_insert
+    pub(crate) fn insert_one_command(&mut self) -> Option<EditorHealth> {
+        self.finish_insert_change();
+        self.context.borrow_mut().restart_insert = true;
+        self.change_mode(EditorModes::Normal)
+    }
+    pub(crate) fn resume_insert(&mut self) {
+        let op = Operator::new(String::from("i"), operations::insert::replay_insert);
+        op.borrow_mut().count = 1;
+        let mut c = self.context.borrow_mut();
+        c.restart_insert = false;
+        c.insert_change = Some(op);
+        c.mode = EditorModes::Insert;
+    }
+    // Places the cursor where an insert entered with `k` starts typing
+    pub(crate) fn position_for_insert(&mut self, k: u8) {
+        let direction = match k {
+            b'I' => CursorDirections::LineBegin,
+            b'A' => CursorDirections::LineEnd,
+            b'a' => CursorDirections::Right,
+            b'i' => return,
+            _ => unreachable!(),
+        };
+        self.cursor
+            .borrow_mut()
+            .move_cursor(&self.data.new_lines, direction, 1)
+            .unwrap();
+    }
+    pub(crate) fn enter_replace_mode(&mut self) -> Option<EditorHealth> {
+        let op = Operator::new(String::from("R"), operations::insert::replay_overtype);
+        op.borrow_mut().count = self.context.borrow().count();
+        let mut c = self.context.borrow_mut();
+        c.insert_change = Some(op);
+        c.replace_backup.clear();
+        c.mode = EditorModes::Replace;
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn handle_replace_mode(&mut self, k: u8) -> Option<EditorHealth> {
+        if k != b'\x1B' {
+            if let Some(op) = self.context.borrow().insert_change.as_ref() {
+                op.borrow_mut().inserted.push(k);
+            }
+        }
+        match k {
+            b'\x1B' => {
+                let op = self.context.borrow_mut().insert_change.take();
+                if let Some(op) = op {
+                    let count = op.borrow().count;
+                    for _ in 1..count {
+                        operations::insert::overtype_keys(
+                            &self.cursor,
+                            &mut self.data,
+                            &op.borrow().inserted,
+                        );
+                    }
+                    self.context.borrow_mut().last_change = Some(op);
+                }
+                self.mark_here('^');
+                self.change_mode(EditorModes::Normal)
+            }
+            // Only what was typed in this replace can be taken back, before it the
+            // cursor just moves
+            b'\x7F' => {
+                let backup = self.context.borrow_mut().replace_backup.pop();
+                match backup {
+                    Some(original) => {
+                        operations::insert::restore_char(&self.cursor, &mut self.data, original)
+                    }
+                    None => self
+                        .cursor
+                        .borrow_mut()
+                        .move_cursor(&self.data.new_lines, CursorDirections::Left, 1)
+                        .unwrap(),
+                }
+                Some(EditorHealth::Healthy)
+            }
+            _ => {
+                self.context.borrow_mut().dirty = 1;
+                let original = operations::insert::replace_char(&self.cursor, &mut self.data, k);
+                self.context.borrow_mut().replace_backup.push(original);
+                Some(EditorHealth::Healthy)
+            }
+        }
+    }
+    pub(crate) fn move_cursor_insert(&mut self, k: u8) -> Option<EditorHealth> {
+        self.position_for_insert(k);
+        let op = Operator::new((k as char).to_string(), operations::insert::replay_insert);
+        op.borrow_mut().count = self.context.borrow().count();
+        self.context.borrow_mut().insert_change = Some(op);
+        self.change_mode(EditorModes::Insert);
+        Some(EditorHealth::Healthy)
+    }
+    fn current_position(&self) -> Position {
+        Position {
+            file: self.context.borrow().files.clone(),
+            offset: operations::normal::cursor_offset(&self.cursor, &self.data),
+        }
+    }
+    // m{a-zA-Z}, also sets ' ` [ ] < > like vim does
+    pub(crate) fn set_mark(&mut self, k: u8) -> Option<EditorHealth> {
+        let name = k as char;
+        if !Marks::is_valid(name) || name == '.' || name == '^' {
+            return Some(EditorHealth::Healthy);
+        }
+        self.mark_here(name);
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn mark_here(&mut self, name: char) {
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        let position = self.current_position();
+        self.context
+            .borrow_mut()
+            .marks
+            .set(name, position.offset, &position.file);
+    }
+    // q{reg} starts recording typed keys into a register, the next q stops
+    pub(crate) fn stop_recording(&mut self) -> Option<EditorHealth> {
+        let recording = self.context.borrow_mut().input.recording.take();
+        let (name, mut keys) = match recording {
+            Some(recording) => recording,
+            None => return self.await_key(Pending::Record),
+        };
+        // The q that stopped the recording isn't part of it
+        keys.pop();
+        self.context.borrow_mut().registers.record(name, keys);
+        Some(EditorHealth::Healthy)
+    }
+    pub(crate) fn start_recording(&mut self, k: u8) -> Option<EditorHealth> {
+        if k.is_ascii_alphanumeric() {
+            self.context.borrow_mut().input.recording = Some((k as char, Vec::new()));
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // @{reg} types the register count times, @@ plays the last one again and @: repeats
+    // the last command line
+    pub(crate) fn play_macro(&mut self, k: u8) -> Option<EditorHealth> {
+        let name = match k {
+            b'@' => self.context.borrow().registers.last_macro,
+            k if Registers::is_valid(k as char) => Some(k as char),
+            _ => None,
+        };
+        let name = match name {
+            Some(name) => name,
+            None => return Some(EditorHealth::Healthy),
+        };
+        let register = {
+            let c = self.context.borrow();
+            c.registers.get(Some(name), &c.files)
+        };
+        let mut keys = match register {
+            Some(register) => register.text,
+            None => {
+                self.context.borrow_mut().input.abort();
+                return Some(EditorHealth::Healthy);
+            }
+        };
+        if name == ':' {
+            keys.insert(0, b':');
+            keys.push(b'\r');
+        }
+        self.context.borrow_mut().registers.last_macro = Some(name);
+        let count = self.context.borrow().count();
+        self.context
+            .borrow_mut()
+            .input
+            .push_front(&keys.repeat(count), KeySource::Macro);
+        Some(EditorHealth::Healthy)
+    }
+    // Keys to run as if typed once the terminal is up, like vim -s
+    pub(crate) fn feed_script(&mut self, keys: &[u8]) {
+        self.context
+            .borrow_mut()
+            .input
+            .push_back(keys, KeySource::Script);
+    }
+    // Adds the cursor position to the jump list and makes it the ' mark
+    pub(crate) fn remember_jump(&mut self) {
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        let position = self.current_position();
+        let mut c = self.context.borrow_mut();
+        c.marks.set('\'', position.offset, &position.file);
+        c.marks.push_jump(position, &self.data.new_lines);
+    }
+    // `x goes to the exact position of mark x, 'x to the first non blank of its line
+    pub(crate) fn jump_to_mark(&mut self, k: u8, name: u8) -> Option<EditorHealth> {
+        let name = name as char;
+        if !Marks::is_valid(name) {
+            return Some(EditorHealth::Healthy);
+        }
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        let target = {
+            let c = self.context.borrow();
+            c.marks.get(name, &c.files)
+        };
+        let target = match target {
+            Some(t) => t,
+            None => {
+                self.set_status_message("E20: Mark not set");
+                self.context.borrow_mut().input.abort();
+                return Some(EditorHealth::Healthy);
+            }
+        };
+        self.remember_jump();
+        if self.go_to(&target) && k == b'\'' {
+            let y = self.cursor.borrow().absy();
+            let x = operations::normal::first_non_blank(&self.cursor, &self.data, y);
+            self.cursor.borrow_mut().set_x(x);
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // Ctrl-O goes back through the jump list, Ctrl-I (Tab) forward again
+    pub(crate) fn walk_jump_list(&mut self, k: u8) -> Option<EditorHealth> {
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        let count = self.context.borrow().count();
+        for _ in 0..count {
+            let current = self.current_position();
+            let target = {
+                let mut c = self.context.borrow_mut();
+                match k == Keys::cntrl(b'o') {
+                    true => c.marks.jump_back(current, &self.data.new_lines),
+                    false => c.marks.jump_forward(),
+                }
+            };
+            match target {
+                Some(target) if self.go_to(&target) => (),
+                _ => {
+                    self.context.borrow_mut().input.abort();
+                    break;
+                }
+            }
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // g; goes to older changes, g, to newer ones
+    pub(crate) fn walk_changes(&mut self, k: u8) -> Option<EditorHealth> {
+        operations::normal::sync_marks(&self.context, &mut self.data);
+        let count = self.context.borrow().count() as isize;
+        let step = if k == b';' { -count } else { count };
+        let target = self.context.borrow_mut().marks.walk_changes(step);
+        match target {
+            Ok(offset) => {
+                let offset = offset.min(self.data.buffer.len());
+                self.cursor
+                    .borrow_mut()
+                    .set_file_index(&self.data.new_lines, offset);
+            }
+            Err(message) => {
+                self.set_status_message(message);
+                self.context.borrow_mut().input.abort();
+            }
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // Puts the cursor on `target`, loading its file first when it is another one
+    pub(crate) fn go_to(&mut self, target: &Position) -> bool {
+        let file = self.context.borrow().files.clone();
+        if target.file != file {
+            if self.context.borrow().dirty != 0 {
+                self.set_status_message("E37: No write since last change");
+                return false;
+            }
+            if self.switch_file(&target.file).is_err() {
+                self.set_status_message(&format!("Can't open {}", target.file));
+                return false;
+            }
+        }
+        let offset = target.offset.min(self.data.buffer.len());
+        self.cursor
+            .borrow_mut()
+            .set_file_index(&self.data.new_lines, offset);
+        true
+    }
+    // Loads file `index` of the argument list, changes are only dropped with `force`
+    pub(crate) fn edit_arg(&mut self, index: usize, force: bool) {
+        let (file, count) = {
+            let c = self.context.borrow();
+            (c.arglist[index].clone(), c.arglist.len())
+        };
+        if self.context.borrow().dirty != 0 && !force {
+            self.set_status_message("E37: No write since last change (add ! to override)");
+            return;
+        }
+        if let Err(err) = self.switch_file(&file) {
+            self.set_status_message(&format!("Can't open {}: {}", file, err));
+            return;
+        }
+        self.context.borrow_mut().arg_index = index;
+        self.set_status_message(&format!("\"{}\" ({} of {})", file, index + 1, count));
+    }
+    // Replaces the buffer with another file, the marks of the old one go. The file is
+    // read first, when that fails the buffer stays as it was.
+    pub(crate) fn switch_file(&mut self, file: &str) -> std::io::Result<()> {
+        let bytes = Self::read_file(file)?;
+        self.data.free();
+        self.data.update_buffers();
+        self.data.edits.clear();
+        self.cursor.borrow_mut().set_x(0);
+        self.cursor.borrow_mut().set_y(0);
+        self.context.borrow_mut().marks.clear_local();
+        self.context.borrow_mut().dirty = 0;
+        self.load(file, bytes);
+        Ok(())
+    }
+    // o and O
+    pub(crate) fn open_line_insert(&mut self, k: u8) -> Option<EditorHealth> {
+        operations::normal::open_line(&self.context, &self.cursor, &mut self.data, k == b'o');
+        let op = Operator::new(
+            (k as char).to_string(),
+            operations::normal::replay_open_line,
+        );
+        op.borrow_mut().count = self.context.borrow().count();
+        self.context.borrow_mut().insert_change = Some(op);
+        self.change_mode(EditorModes::Insert)
+    }
+    // c{motion}, C, S and s: delete, then type the replacement in insert mode
+    pub(crate) fn start_change(&mut self, signature: &str, motion: &str) -> Option<EditorHealth> {
+        let count = self.context.borrow().count();
+        let changed = operations::normal::apply_change(
+            &self.context,
+            &self.cursor,
+            &self.terminal,
+            &mut self.data,
+            motion,
+            count,
+        );
+        if !changed {
+            return Some(EditorHealth::Healthy);
+        }
+        let op = Operator::new(signature.to_string(), operations::normal::replay_change);
+        op.borrow_mut().motion = motion.to_string();
+        op.borrow_mut().count = count;
+        op.borrow_mut().register = self.context.borrow().registers.selected;
+        self.context.borrow_mut().insert_change = Some(op);
+        self.change_mode(EditorModes::Insert)
+    }
+    // J and gJ
+    pub(crate) fn join_lines(&mut self, signature: &str) -> Option<EditorHealth> {
+        let count = self.context.borrow().count();
+        let y = self.cursor.borrow().absy();
+        let joined = operations::normal::join_lines(
+            &self.context,
+            &self.cursor,
+            &mut self.data,
+            y,
+            count,
+            signature == "J",
+        );
+        if joined {
+            let op = Operator::new(signature.to_string(), operations::normal::replay_join);
+            op.borrow_mut().count = count;
+            self.context.borrow_mut().last_change = Some(op);
+        }
+        Some(EditorHealth::Healthy)
+    }
+    // An o or O left without typing anything drops the indent it put on the new line
+    fn strip_unused_indent(&mut self) {
+        let y = self.cursor.borrow().absy();
+        let indent = operations::normal::indent_of(&self.cursor, &self.data, y);
+        let (line_begin, line_end) = self
+            .cursor
+            .borrow()
+            .calculate_row_of_insert_indices(y, &self.data.new_lines);
+        if indent > 0 && line_begin + indent == line_end {
+            self.data.replace_slice(line_begin..line_end, b"");
+            self.cursor.borrow_mut().set_x(0);
+        }
+    }
+    // Leaving insert mode completes the change: `3ifoo<Esc>` types the text twice more
+    // and the whole thing becomes what `.` repeats
+    pub(crate) fn finish_insert_change(&mut self) {
+        let op = match self.context.borrow_mut().insert_change.take() {
+            Some(op) => op,
+            None => return,
+        };
+        let count = op.borrow().count;
+        let signature = op.borrow().signature.clone();
+        let opened = signature == "o" || signature == "O";
+        if opened && op.borrow().inserted.is_empty() {
+            self.strip_unused_indent();
+        }
+        for _ in 1..count {
+            match signature.as_str() {
+                "i" | "a" | "I" | "A" => {}
+                "o" | "O" => {
+                    operations::normal::open_line(
+                        &self.context,
+                        &self.cursor,
+                        &mut self.data,
+                        true,
+                    );
+                    if op.borrow().inserted.is_empty() {
+                        self.strip_unused_indent();
+                    }
+                }
+                // A count on a change is part of what it deletes
+                _ => break,
+            }
+            operations::insert::type_keys(
+                &self.context,
+                &self.cursor,
+                &mut self.data,
+                &op.borrow().inserted,
+            );
+        }
+        let block = self.context.borrow_mut().block_insert.take();
+        if let Some(block) = block {
+            operations::visual::finish_block_insert(
+                &self.context,
+                &self.cursor,
+                &mut self.data,
+                block,
+                &op.borrow().inserted,
+            );
+            // The whole block is one change for `.`, a changed one remembers how many
+            // columns went in its motion
+            let mut o = op.borrow_mut();
+            o.count = block.last - block.first + 1;
+            if block.width > 0 {
+                o.signature = String::from("\x16c");
+                o.motion = match block.to_eol {
+                    true => String::from("$"),
+                    false => block.width.to_string(),
+                };
+                o.ftor = operations::visual::replay_block_change;
+            } else {
+                o.signature = format!("\x16{}", if block.append { 'A' } else { 'I' });
+                o.ftor = operations::visual::replay_block_insert;
+            }
+        }
+        self.context.borrow_mut().last_change = Some(op);
+    }
+    pub(crate) fn navigate(&mut self, k: u8) -> Option<EditorHealth> {
+        // TODO: Make here better A lot of repetittions
+        log::debug!("{:?}", self.cursor.borrow());
+        let before = (self.cursor.borrow().absx(), self.cursor.borrow().absy());
+        match k {
+            b'h' => self
+                .cursor
+                .borrow_mut()
+                .move_cursor(&self.data.new_lines, CursorDirections::Left, 1)
                 .unwrap(),
             b'j' => self
                 .cursor
@@ -454,6 +2404,245 @@ impl Editor {
                 .unwrap(),
             _ => unreachable!(),
         }
+        // A motion that can't move fails, which ends a macro running it
+        if before == (self.cursor.borrow().absx(), self.cursor.borrow().absy()) {
+            self.context.borrow_mut().input.abort();
+        }
         Some(EditorHealth::Healthy)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::testing::{editor, editor_on, temp_dir, type_script};
+    use crate::editor::Pending;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn macro_records_and_plays_with_a_count() {
+        let mut editor = editor(b"a\nb\nc\nd\ne\n");
+        type_script(&mut editor, b"qaA;\x1Bjq2@a");
+        assert_eq!(editor.data.buffer, b"a;\nb;\nc;\nd\ne\n");
+        let register = editor.context.borrow().registers.get(Some('a'), "");
+        assert_eq!(register.map(|r| r.text), Some(b"A;\x1Bj".to_vec()));
+        type_script(&mut editor, b"@@");
+        assert_eq!(editor.data.buffer, b"a;\nb;\nc;\nd;\ne\n");
+    }
+
+    #[test]
+    fn failing_motion_ends_the_macro() {
+        let mut editor = editor(b"abc\nabc\nabc\n");
+        type_script(&mut editor, b"qqxjq99@q");
+        assert_eq!(editor.data.buffer, b"bc\nbc\nbc\n");
+        assert!(editor.context.borrow().input.is_empty());
+    }
+
+    #[test]
+    fn uppercase_register_appends_to_the_macro() {
+        let mut editor = editor(b"abc\n");
+        type_script(&mut editor, b"qaxqqAxq");
+        let register = editor.context.borrow().registers.get(Some('a'), "");
+        assert_eq!(register.map(|r| r.text), Some(b"xx".to_vec()));
+    }
+
+    #[test]
+    fn plus_search_takes_a_match_on_the_first_line() {
+        let mut editor = editor(b"foo\nbar\nfoo\n");
+        editor.search_from_top("/foo");
+        assert_eq!(editor.cursor.borrow().absy(), 0);
+        editor.search_from_top("/bar");
+        assert_eq!(editor.cursor.borrow().absy(), 1);
+        editor.search_from_top("/nope");
+        assert_eq!(editor.cursor.borrow().absy(), 1);
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E486: Pattern not found: nope"
+        );
+    }
+
+    #[test]
+    fn unwritable_file_opens_readonly_and_w_bang_writes_it() {
+        let dir = temp_dir("readonly");
+        let file = dir.join("file.txt");
+        fs::write(&file, b"old\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o444)).unwrap();
+        // Root may write any file
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let mut editor = editor_on(&file);
+        assert!(editor.context.borrow().options.flag("readonly"));
+        assert!(editor
+            .context
+            .borrow()
+            .status_message
+            .contains("[readonly]"));
+        type_script(&mut editor, b"x");
+        editor.execute_command("w");
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E45: 'readonly' option is set (add ! to override)"
+        );
+        assert_eq!(fs::read(&file).unwrap(), b"old\n");
+        editor.execute_command("w!");
+        assert_eq!(fs::read(&file).unwrap(), b"ld\n");
+    }
+
+    #[test]
+    fn readonly_refuses_w_without_bang() {
+        let dir = temp_dir("set-readonly");
+        let file = dir.join("file.txt");
+        fs::write(&file, b"old\n").unwrap();
+        let mut editor = editor_on(&file);
+        assert!(!editor.context.borrow().options.flag("readonly"));
+        editor.execute_command("set readonly");
+        type_script(&mut editor, b"x");
+        editor.execute_command("w");
+        assert!(editor.context.borrow().status_message.starts_with("E45"));
+        assert_eq!(fs::read(&file).unwrap(), b"old\n");
+        editor.execute_command("w!");
+        assert_eq!(fs::read(&file).unwrap(), b"ld\n");
+    }
+
+    #[test]
+    fn opening_a_missing_file_does_not_create_it() {
+        let file = temp_dir("missing").join("new.txt");
+        let editor = editor_on(&file);
+        assert!(!file.exists());
+        assert!(editor.context.borrow().status_message.ends_with("[New]"));
+    }
+
+    #[test]
+    fn nomodifiable_refuses_changes() {
+        let mut editor = editor(b"text\n");
+        editor.execute_command("set nomodifiable");
+        type_script(&mut editor, b"x");
+        assert_eq!(
+            editor.context.borrow().status_message,
+            "E21: Cannot make changes, 'modifiable' is off"
+        );
+        assert_eq!(editor.data.buffer, b"text\n");
+    }
+
+    #[test]
+    fn o_keeps_a_modified_buffer() {
+        let mut editor = editor(b"changed\n");
+        type_script(&mut editor, b"x");
+        editor.execute_command("o /nonexistent/other");
+        assert_eq!(editor.data.buffer, b"hanged\n");
+        assert!(editor.context.borrow().status_message.starts_with("E37"));
+    }
+
+    #[test]
+    fn failed_switch_keeps_the_buffer() {
+        let dir = temp_dir("switch-failed");
+        let file = dir.join("file.txt");
+        fs::write(&file, b"kept\n").unwrap();
+        let mut editor = editor_on(&file);
+        let file = file.to_str().unwrap();
+        type_script(&mut editor, b"mx");
+        editor.execute_command(&format!("e {}", dir.display()));
+        assert!(editor
+            .context
+            .borrow()
+            .status_message
+            .starts_with("Can't open"));
+        assert_eq!(editor.data.buffer, b"kept\n");
+        assert_eq!(editor.context.borrow().files, file);
+        assert!(editor.context.borrow().marks.get('x', file).is_some());
+        // Writing it back doesn't lose anything
+        editor.execute_command("w");
+        assert_eq!(fs::read(file).unwrap(), b"kept\n");
+    }
+
+    #[test]
+    fn e_switches_files_and_e_bang_drops_changes() {
+        let dir = temp_dir("switch");
+        let (one, two) = (dir.join("one"), dir.join("two"));
+        fs::write(&one, b"one\n").unwrap();
+        fs::write(&two, b"two\n").unwrap();
+        let mut editor = editor_on(&one);
+        type_script(&mut editor, b"x");
+        editor.execute_command(&format!("e {}", two.display()));
+        assert!(editor.context.borrow().status_message.starts_with("E37"));
+        editor.execute_command("e!");
+        assert_eq!(editor.data.buffer, b"one\n");
+        assert_eq!(editor.context.borrow().dirty, 0);
+        editor.execute_command(&format!("e {}", two.display()));
+        assert_eq!(editor.data.buffer, b"two\n");
+    }
+
+    #[test]
+    fn changed_file_is_noticed_and_autoread_loads_it() {
+        let file = temp_dir("changed").join("file.txt");
+        fs::write(&file, b"old\n").unwrap();
+        let mut editor = editor_on(&file);
+        assert!(!editor.file_changed());
+        fs::write(&file, b"newer\n").unwrap();
+        editor.check_file();
+        assert!(editor.context.borrow().status_message.starts_with("W11"));
+        assert_eq!(editor.data.buffer, b"old\n");
+        editor.execute_command("set autoread");
+        editor.stamp_file();
+        fs::write(&file, b"newest\n").unwrap();
+        editor.check_file();
+        assert_eq!(editor.data.buffer, b"newest\n");
+    }
+
+    #[test]
+    fn recover_answers_the_swap_question_and_takes_keys_after() {
+        let dir = temp_dir("recover");
+        let file = dir.join("file.txt");
+        fs::write(&file, b"old\n").unwrap();
+        // Left behind by a yim that is gone
+        fs::write(
+            dir.join(".file.txt.swp"),
+            b"yim swap file\npid 0\nfile file.txt\n\nrecovered\n",
+        )
+        .unwrap();
+        let mut editor = editor_on(&file);
+        editor.execute_command("set swapfile");
+        // What yim -r file does
+        editor.open(file.to_str().unwrap()).unwrap();
+        assert_eq!(editor.context.borrow().pending, Pending::SwapFound);
+        editor.recover();
+        assert_eq!(editor.context.borrow().pending, Pending::Nothing);
+        assert_eq!(editor.data.buffer, b"recovered\n");
+        type_script(&mut editor, b"x");
+        assert_eq!(editor.data.buffer, b"ecovered\n");
+        editor.remove_swap();
+    }
+
+    #[test]
+    fn noeol_file_round_trips_with_nofixeol() {
+        let dir = temp_dir("noeol");
+        let file = dir.join("file.txt");
+        fs::write(&file, b"one\ntwo").unwrap();
+        let mut editor = editor_on(&file);
+        assert_eq!(editor.data.buffer, b"one\ntwo\n");
+        assert!(!editor.context.borrow().options.flag("endofline"));
+        // Loading isn't an edit, it leaves no [ ] or . marks behind
+        assert!(editor.data.edits.is_empty());
+        editor.execute_command("set nofixeol");
+        editor.execute_command("w");
+        assert_eq!(fs::read(&file).unwrap(), b"one\ntwo");
+        editor.execute_command("set fixeol");
+        editor.execute_command("w");
+        assert_eq!(fs::read(&file).unwrap(), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn empty_buffer_without_eol_writes_nothing() {
+        let dir = temp_dir("empty-noeol");
+        let file = dir.join("file.txt");
+        let mut editor = editor_on(&file);
+        editor.execute_command("set nofixeol");
+        editor.execute_command("w");
+        assert_eq!(fs::read(&file).unwrap(), b"");
+        // Not even the newline every buffer gets
+        editor.data.free();
+        editor.execute_command("w!");
+        assert_eq!(fs::read(&file).unwrap(), b"");
+    }
+}
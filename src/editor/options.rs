@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Options set with :set. Every option is declared once in OPTIONS with its type,
+// default and scope. Global options have one value. Buffer local ones have a global
+// value too, which a newly opened file starts from; :set changes both, :setlocal and
+// :setglobal just one of them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionKind {
+    Bool,
+    Number,
+    String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionScope {
+    Global,
+    Buffer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Bool(bool),
+    Number(i64),
+    String(String),
+}
+
+pub struct OptionDef {
+    pub name: &'static str,
+    pub short: &'static str,
+    pub kind: OptionKind,
+    // "on"/"off" for booleans
+    pub default: &'static str,
+    pub scope: OptionScope,
+    // Lowest number accepted
+    pub min: i64,
+    // What a string option may be set to, anything when empty
+    pub values: &'static [&'static str],
+}
+
+pub const OPTIONS: &[OptionDef] = &[
+    OptionDef {
+        name: "number",
+        short: "nu",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "numberwidth",
+        short: "nuw",
+        kind: OptionKind::Number,
+        default: "4",
+        scope: OptionScope::Global,
+        min: 2,
+        values: &[],
+    },
+    // Columns moved by > and <, Ctrl-T and Ctrl-D
+    OptionDef {
+        name: "shiftwidth",
+        short: "sw",
+        kind: OptionKind::Number,
+        default: "4",
+        scope: OptionScope::Buffer,
+        min: 1,
+        values: &[],
+    },
+    // Terminals put tab stops every 8 columns
+    OptionDef {
+        name: "tabstop",
+        short: "ts",
+        kind: OptionKind::Number,
+        default: "8",
+        scope: OptionScope::Buffer,
+        min: 1,
+        values: &[],
+    },
+    // Wait for the rest of a mapping at all, and for how many milliseconds
+    OptionDef {
+        name: "timeout",
+        short: "to",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "timeoutlen",
+        short: "tm",
+        kind: OptionKind::Number,
+        default: "1000",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    // Characters words are made of, for Ctrl-W and abbreviations. Bytes past ASCII
+    // are always part of words, they belong to UTF-8 characters.
+    OptionDef {
+        name: "iskeyword",
+        short: "isk",
+        kind: OptionKind::String,
+        default: "@,48-57,_,192-255",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    // Keep the last version of a file as file~ once it is written over, and make that
+    // copy anyway while a write that can't be atomic is in progress
+    OptionDef {
+        name: "backup",
+        short: "bk",
+        kind: OptionKind::Bool,
+        default: "off",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "writebackup",
+        short: "wb",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "backupext",
+        short: "bex",
+        kind: OptionKind::String,
+        default: "~",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    // How a file is written: "no" writes a new file and renames it over the old one,
+    // "yes" overwrites the old one and keeps its links, "auto" renames unless there
+    // are links or an owner and group to keep, or the directory can't take a new file
+    OptionDef {
+        name: "backupcopy",
+        short: "bkc",
+        kind: OptionKind::String,
+        default: "auto",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &["yes", "no", "auto"],
+    },
+    // Load a file changed by another program again when the buffer has no changes
+    OptionDef {
+        name: "autoread",
+        short: "ar",
+        kind: OptionKind::Bool,
+        default: "off",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    // Keep the text in a swap file for recovery, written again after 'updatecount'
+    // keys or 'updatetime' milliseconds without one. updatecount=0 is no swap files.
+    OptionDef {
+        name: "swapfile",
+        short: "swf",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "updatecount",
+        short: "uc",
+        kind: OptionKind::Number,
+        default: "200",
+        scope: OptionScope::Global,
+        min: 0,
+        values: &[],
+    },
+    OptionDef {
+        name: "updatetime",
+        short: "ut",
+        kind: OptionKind::Number,
+        default: "4000",
+        scope: OptionScope::Global,
+        min: 1,
+        values: &[],
+    },
+    // :w needs a ! to write the buffer, set for files yim can't write and by yim -R
+    OptionDef {
+        name: "readonly",
+        short: "ro",
+        kind: OptionKind::Bool,
+        default: "off",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    // The line ends written to the file, found out when it is read. Changing it
+    // changes the buffer.
+    OptionDef {
+        name: "fileformat",
+        short: "ff",
+        kind: OptionKind::String,
+        default: "unix",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &["unix", "dos", "mac"],
+    },
+    // The encoding of the file, found out when it is read, and whether it starts with a
+    // byte order mark. Changing them changes the buffer. UTF-16 without a mark is only
+    // recognised when at least half its characters are below U+0100, other text in it
+    // is not. Text that isn't UTF-8 is read as latin1 with a warning, or kept as raw
+    // bytes with fenc=utf-8 when it has UTF-8 characters too, written back unchanged.
+    OptionDef {
+        name: "fileencoding",
+        short: "fenc",
+        kind: OptionKind::String,
+        default: "utf-8",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &["utf-8", "utf-16le", "utf-16be", "latin1"],
+    },
+    OptionDef {
+        name: "bomb",
+        short: "bomb",
+        kind: OptionKind::Bool,
+        default: "off",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    // Whether the file ends in a newline, off for files read without one
+    OptionDef {
+        name: "endofline",
+        short: "eol",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    // Write the newline at the end anyway, without it 'noeol' files stay as they were
+    OptionDef {
+        name: "fixendofline",
+        short: "fixeol",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+    // Off, the text can't be changed at all
+    OptionDef {
+        name: "modifiable",
+        short: "ma",
+        kind: OptionKind::Bool,
+        default: "on",
+        scope: OptionScope::Buffer,
+        min: 0,
+        values: &[],
+    },
+];
+
+impl OptionDef {
+    fn find(name: &str) -> Option<&'static OptionDef> {
+        OPTIONS.iter().find(|o| o.name == name || o.short == name)
+    }
+    fn default_value(&self) -> OptionValue {
+        match self.kind {
+            OptionKind::Bool => OptionValue::Bool(self.default == "on"),
+            OptionKind::Number => OptionValue::Number(self.default.parse().unwrap_or(0)),
+            OptionKind::String => OptionValue::String(self.default.to_string()),
+        }
+    }
+}
+
+// Which values :set touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetScope {
+    Both,
+    Local,
+    Global,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    global: HashMap<&'static str, OptionValue>,
+    local: HashMap<&'static str, OptionValue>,
+    // iskeyword in effect, for ASCII
+    keywords: [bool; 128],
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        let global: HashMap<_, _> = OPTIONS
+            .iter()
+            .map(|o| (o.name, o.default_value()))
+            .collect();
+        let local = OPTIONS
+            .iter()
+            .filter(|o| o.scope == OptionScope::Buffer)
+            .map(|o| (o.name, o.default_value()))
+            .collect();
+        let mut options = Self {
+            global,
+            local,
+            keywords: [false; 128],
+        };
+        options.update_keywords();
+        options
+    }
+}
+
+impl Options {
+    // The value in effect, the buffer's own for buffer local options
+    pub(crate) fn get(&self, name: &str) -> &OptionValue {
+        self.local
+            .get(name)
+            .or_else(|| self.global.get(name))
+            .unwrap_or_else(|| panic!("option {} is not in OPTIONS", name))
+    }
+    pub(crate) fn flag(&self, name: &str) -> bool {
+        matches!(self.get(name), OptionValue::Bool(true))
+    }
+    pub(crate) fn string(&self, name: &str) -> &str {
+        match self.get(name) {
+            OptionValue::String(s) => s,
+            _ => "",
+        }
+    }
+    pub(crate) fn number(&self, name: &str) -> usize {
+        match self.get(name) {
+            OptionValue::Number(n) => *n as usize,
+            _ => 0,
+        }
+    }
+    // For what open finds out about a file, like it being read only
+    pub(crate) fn set_local(&mut self, name: &'static str, value: OptionValue) {
+        self.local.insert(name, value);
+    }
+    pub(crate) fn shift_width(&self) -> usize {
+        self.number("shiftwidth")
+    }
+    pub(crate) fn tab_stop(&self) -> usize {
+        self.number("tabstop")
+    }
+    pub(crate) fn is_keyword(&self, c: u8) -> bool {
+        c >= 0x80 || self.keywords[c as usize]
+    }
+    fn update_keywords(&mut self) {
+        let spec = match self.get("iskeyword") {
+            OptionValue::String(spec) => spec.clone(),
+            _ => String::new(),
+        };
+        self.keywords = parse_keywords(&spec).unwrap_or([false; 128]);
+    }
+    // A new file starts from the global values of buffer local options
+    pub(crate) fn reset_local(&mut self) {
+        for o in OPTIONS.iter().filter(|o| o.scope == OptionScope::Buffer) {
+            self.local.insert(o.name, self.global[o.name].clone());
+        }
+        self.update_keywords();
+    }
+    // One :set argument: `opt`, `noopt`, `invopt`, `opt!`, `opt&`, `opt?`, `opt=val`
+    // and `opt+=val`, `opt-=val`, `opt^=val`. Returns the line to show for a query.
+    pub(crate) fn set(&mut self, arg: &str, scope: SetScope) -> Result<Option<String>, String> {
+        let split = arg.find(['=', ':']).map(|i| match i {
+            i if i > 0 && "+-^".contains(&arg[i - 1..i]) => {
+                (&arg[..i - 1], &arg[i - 1..i], &arg[i + 1..])
+            }
+            i => (&arg[..i], "=", &arg[i + 1..]),
+        });
+        let (name, operator, value) = match split {
+            Some(split) => split,
+            None => (arg, "", ""),
+        };
+        let (name, suffix) = match name.strip_suffix(['?', '!', '&']) {
+            Some(stripped) if operator.is_empty() => (stripped, &name[stripped.len()..]),
+            _ => (name, ""),
+        };
+        let (name, prefix) = match (
+            OptionDef::find(name),
+            name.strip_prefix("no"),
+            name.strip_prefix("inv"),
+        ) {
+            (Some(o), _, _) => (o, ""),
+            (None, Some(rest), _) => (OptionDef::find(rest).ok_or_else(|| unknown(arg))?, "no"),
+            (None, _, Some(rest)) => (OptionDef::find(rest).ok_or_else(|| unknown(arg))?, "inv"),
+            _ => return Err(unknown(arg)),
+        };
+        let current = self.value(name, scope).clone();
+        let new = match (name.kind, prefix, suffix, operator) {
+            (_, "", "?", "") => return Ok(Some(self.show(name, scope))),
+            (_, "", "&", "") => name.default_value(),
+            (OptionKind::Bool, "", "", "") => OptionValue::Bool(true),
+            (OptionKind::Bool, "no", "", "") => OptionValue::Bool(false),
+            (OptionKind::Bool, "inv", "", "") | (OptionKind::Bool, "", "!", "") => {
+                OptionValue::Bool(current != OptionValue::Bool(true))
+            }
+            // Without a value a number or string option is shown
+            (_, "", "", "") => return Ok(Some(self.show(name, scope))),
+            (OptionKind::Number, "", "", operator) => {
+                let n: i64 = value
+                    .parse()
+                    .map_err(|_| format!("E521: Number required after =: {}", arg))?;
+                let OptionValue::Number(old) = current else {
+                    unreachable!("number option holding another type")
+                };
+                let n = match operator {
+                    "+" => old + n,
+                    "-" => old - n,
+                    "^" => old * n,
+                    _ => n,
+                };
+                if n < name.min {
+                    return Err(match name.min {
+                        0 => format!("E487: Argument must be positive: {}", arg),
+                        _ => format!("E474: Invalid argument: {}", arg),
+                    });
+                }
+                OptionValue::Number(n)
+            }
+            (OptionKind::String, "", "", operator) => {
+                let OptionValue::String(old) = current else {
+                    unreachable!("string option holding another type")
+                };
+                // String options are comma separated lists as far as += -= ^= go
+                let mut items: Vec<&str> = old.split(',').filter(|i| !i.is_empty()).collect();
+                let value = match operator {
+                    "+" | "^" if items.contains(&value) => old.clone(),
+                    "+" => {
+                        items.push(value);
+                        items.join(",")
+                    }
+                    "^" => {
+                        items.insert(0, value);
+                        items.join(",")
+                    }
+                    "-" => {
+                        items.retain(|i| *i != value);
+                        items.join(",")
+                    }
+                    _ => value.to_string(),
+                };
+                let listed = name.values.is_empty() || name.values.contains(&value.as_str());
+                if !listed || (name.name == "iskeyword" && parse_keywords(&value).is_none()) {
+                    return Err(format!("E474: Invalid argument: {}", arg));
+                }
+                OptionValue::String(value)
+            }
+            _ => return Err(format!("E474: Invalid argument: {}", arg)),
+        };
+        let local = name.scope == OptionScope::Buffer;
+        if scope != SetScope::Local || !local {
+            self.global.insert(name.name, new.clone());
+        }
+        if scope != SetScope::Global && local {
+            self.local.insert(name.name, new);
+        }
+        self.update_keywords();
+        Ok(None)
+    }
+    fn value(&self, option: &OptionDef, scope: SetScope) -> &OptionValue {
+        match scope {
+            SetScope::Global => &self.global[option.name],
+            _ => self.get(option.name),
+        }
+    }
+    fn show(&self, option: &OptionDef, scope: SetScope) -> String {
+        match self.value(option, scope) {
+            OptionValue::Bool(true) => format!("  {}", option.name),
+            OptionValue::Bool(false) => format!("no{}", option.name),
+            OptionValue::Number(n) => format!("  {}={}", option.name, n),
+            OptionValue::String(s) => format!("  {}={}", option.name, s),
+        }
+    }
+    // :set all lists every option, :set alone the ones changed from their default
+    pub(crate) fn listing(&self, all: bool, scope: SetScope) -> Vec<String> {
+        OPTIONS
+            .iter()
+            .filter(|o| all || *self.value(o, scope) != o.default_value())
+            .map(|o| self.show(o, scope))
+            .collect()
+    }
+}
+
+// :set arguments are separated by spaces, a backslash keeps a space in a value
+pub(crate) fn split_set_args(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut arg = String::new();
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            ' ' | '\t' if !arg.is_empty() => split.push(std::mem::take(&mut arg)),
+            ' ' | '\t' => (),
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        split.push(arg);
+    }
+    split
+}
+
+// iskeyword is a list of `@` (letters), characters, codes and ranges of both, `^`
+// in front of an item takes it away again. None when it doesn't parse.
+fn parse_keywords(spec: &str) -> Option<[bool; 128]> {
+    let mut keywords = [false; 128];
+    for item in spec.split(',').filter(|i| !i.is_empty()) {
+        let (item, on) = match item.strip_prefix('^') {
+            Some(rest) if !rest.is_empty() => (rest, false),
+            _ => (item, true),
+        };
+        let code = |part: &str| -> Option<usize> {
+            match part.parse::<usize>() {
+                Ok(n) => Some(n),
+                Err(_) if part.chars().count() == 1 => Some(part.chars().next()? as usize),
+                Err(_) => None,
+            }
+        };
+        let range = match item {
+            "@" => {
+                for c in (b'a'..=b'z').chain(b'A'..=b'Z') {
+                    keywords[c as usize] = on;
+                }
+                continue;
+            }
+            "@-@" => (b'@' as usize, b'@' as usize),
+            // A range like 48-57 or a-z, its first character may itself be a -
+            item => {
+                let first = item.chars().next()?.len_utf8();
+                match item[first..].split_once('-') {
+                    Some((from, to)) => (code(&item[..first + from.len()])?, code(to)?),
+                    None => (code(item)?, code(item)?),
+                }
+            }
+        };
+        if range.0 > range.1 || range.1 > 255 {
+            return None;
+        }
+        if range.0 < keywords.len() {
+            keywords[range.0..=range.1.min(127)].fill(on);
+        }
+    }
+    Some(keywords)
+}
+
+// $XDG_CONFIG_HOME/yim/yimrc, ~/.config/yim/yimrc without it
+pub(crate) fn yimrc_path() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("yim").join("yimrc"))
+}
+
+fn unknown(arg: &str) -> String {
+    format!("E518: Unknown option: {}", arg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn booleans_take_no_inv_bang_and_ampersand() {
+        let mut options = Options::default();
+        options.set("nonumber", SetScope::Both).unwrap();
+        assert!(!options.flag("number"));
+        options.set("invnu", SetScope::Both).unwrap();
+        assert!(options.flag("number"));
+        options.set("number!", SetScope::Both).unwrap();
+        assert!(!options.flag("number"));
+        options.set("number&", SetScope::Both).unwrap();
+        assert!(options.flag("number"));
+        assert_eq!(
+            options.set("nu?", SetScope::Both),
+            Ok(Some(String::from("  number")))
+        );
+    }
+
+    #[test]
+    fn numbers_add_subtract_and_multiply() {
+        let mut options = Options::default();
+        options.set("sw=2", SetScope::Both).unwrap();
+        options.set("sw+=3", SetScope::Both).unwrap();
+        assert_eq!(options.shift_width(), 5);
+        options.set("sw-=1", SetScope::Both).unwrap();
+        options.set("sw^=2", SetScope::Both).unwrap();
+        assert_eq!(options.shift_width(), 8);
+        assert_eq!(
+            options.set("sw=0", SetScope::Both),
+            Err(String::from("E474: Invalid argument: sw=0"))
+        );
+        assert!(options
+            .set("sw=x", SetScope::Both)
+            .unwrap_err()
+            .starts_with("E521"));
+        assert_eq!(
+            options.set("ts", SetScope::Both),
+            Ok(Some(String::from("  tabstop=8")))
+        );
+    }
+
+    #[test]
+    fn strings_are_comma_lists_for_plus_minus_and_caret() {
+        let mut options = Options::default();
+        options.set("isk=a,b", SetScope::Both).unwrap();
+        options.set("isk+=c", SetScope::Both).unwrap();
+        options.set("isk^=d", SetScope::Both).unwrap();
+        // Already there, nothing changes
+        options.set("isk+=a", SetScope::Both).unwrap();
+        assert_eq!(options.string("iskeyword"), "d,a,b,c");
+        options.set("isk-=b", SetScope::Both).unwrap();
+        assert_eq!(options.string("iskeyword"), "d,a,c");
+        assert!(options.set("ff=amiga", SetScope::Both).is_err());
+    }
+
+    #[test]
+    fn unknown_options_and_bad_prefixes_fail() {
+        let mut options = Options::default();
+        assert_eq!(
+            options.set("nosuch", SetScope::Both),
+            Err(String::from("E518: Unknown option: nosuch"))
+        );
+        assert!(options.set("nosw", SetScope::Both).is_err());
+        assert!(options.set("invts=3", SetScope::Both).is_err());
+    }
+
+    #[test]
+    fn setlocal_and_setglobal_touch_one_value() {
+        let mut options = Options::default();
+        options.set("sw=2", SetScope::Local).unwrap();
+        assert_eq!(options.shift_width(), 2);
+        options.reset_local();
+        assert_eq!(options.shift_width(), 4);
+        options.set("sw=6", SetScope::Global).unwrap();
+        assert_eq!(options.shift_width(), 4);
+        options.reset_local();
+        assert_eq!(options.shift_width(), 6);
+        // Global options have one value whatever the scope
+        options.set("tm=10", SetScope::Local).unwrap();
+        assert_eq!(options.number("timeoutlen"), 10);
+    }
+
+    #[test]
+    fn set_args_split_on_unescaped_blanks() {
+        assert_eq!(split_set_args("  ts=4\tsw=2 "), vec!["ts=4", "sw=2"]);
+        assert_eq!(split_set_args(r"bex=\ old nu"), vec!["bex= old", "nu"]);
+        assert!(split_set_args("").is_empty());
+    }
+
+    #[test]
+    fn iskeyword_items_ranges_and_exclusions() {
+        let keywords = parse_keywords("@,48-57,_,^x,-").unwrap();
+        assert!(keywords[b'a' as usize] && keywords[b'Z' as usize]);
+        assert!(keywords[b'5' as usize] && keywords[b'_' as usize]);
+        assert!(keywords[b'-' as usize]);
+        assert!(!keywords[b'x' as usize] && !keywords[b'.' as usize]);
+        let keywords = parse_keywords("a-c,@-@").unwrap();
+        assert!(keywords[b'b' as usize] && keywords[b'@' as usize]);
+        assert!(!keywords[b'd' as usize]);
+        assert!(parse_keywords("z-a").is_none());
+        assert!(parse_keywords("300").is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+// Registers hold yanked and deleted text the way vim does:
+//  ""        unnamed, points to whatever was written last
+//  "0        last yank
+//  "1..."9   ring of deletes that span lines, "1 being the newest
+//  "-        deletes within a line
+//  "a..."z   named, "A..."Z appends to the same register
+//  "_        black hole, writing there drops the text
+//  "% ": "/  read only: file name, last command line, last search
+// Macros are recorded into and played from the same registers.
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,
+    Blockwise,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum RegisterOp {
+    Yank,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub kind: RegisterKind,
+    // Linewise text always ends with a newline, blockwise rows are separated by one
+    pub text: Vec<u8>,
+}
+
+impl Register {
+    pub fn new(kind: RegisterKind, text: Vec<u8>) -> Self {
+        Self { kind, text }
+    }
+    fn append(&mut self, other: Register) {
+        if other.kind == RegisterKind::Linewise && self.kind != RegisterKind::Linewise {
+            self.text.push(b'\n');
+            self.kind = RegisterKind::Linewise;
+        }
+        self.text.extend(other.text);
+    }
+    // Single line preview with control characters shown as ^X
+    pub(crate) fn preview(&self, width: usize) -> String {
+        let mut out = String::new();
+        for c in String::from_utf8_lossy(&self.text).chars() {
+            if out.len() >= width {
+                break;
+            }
+            match c as u32 {
+                0..=31 => {
+                    out.push('^');
+                    out.push((c as u8 + b'@') as char);
+                }
+                127 => out.push_str("^?"),
+                _ => out.push(c),
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Registers {
+    named: HashMap<char, Register>,
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+    unnamed: Option<char>,
+    pub selected: Option<char>,
+    pub last_command: String,
+    pub last_search: String,
+    // Register @@ plays again
+    pub last_macro: Option<char>,
+}
+
+impl Registers {
+    pub(crate) fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || "\"-_%:/+*".contains(name)
+    }
+    pub(crate) fn is_read_only(name: char) -> bool {
+        "%:/".contains(name)
+    }
+    // `"x` before a command, returns false for names that aren't registers
+    pub(crate) fn select(&mut self, name: char) -> bool {
+        if !Self::is_valid(name) {
+            return false;
+        }
+        self.selected = Some(name);
+        true
+    }
+    pub(crate) fn store(&mut self, op: RegisterOp, kind: RegisterKind, text: Vec<u8>) {
+        let register = Register::new(kind, text);
+        // Deletes spanning lines feed the numbered ring even when a register was named
+        let ring = op == RegisterOp::Delete
+            && (kind != RegisterKind::Charwise || register.text.contains(&b'\n'));
+        match self.selected {
+            Some('_') => (),
+            Some(name) if Self::is_read_only(name) => (),
+            Some(name) if name.is_ascii_uppercase() => {
+                if ring {
+                    self.shift_deletes(register.clone());
+                }
+                let name = name.to_ascii_lowercase();
+                match self.named.get_mut(&name) {
+                    Some(existing) => existing.append(register),
+                    None => {
+                        self.named.insert(name, register);
+                    }
+                }
+                self.unnamed = Some(name);
+            }
+            Some(name) if name != '"' => {
+                if ring && !name.is_ascii_digit() {
+                    self.shift_deletes(register.clone());
+                }
+                self.write(name, register);
+                self.unnamed = Some(name);
+            }
+            _ => match op {
+                RegisterOp::Yank => {
+                    self.numbered[0] = Some(register);
+                    self.unnamed = Some('0');
+                }
+                RegisterOp::Delete if ring => {
+                    self.shift_deletes(register);
+                    self.unnamed = Some('1');
+                }
+                RegisterOp::Delete => {
+                    self.small_delete = Some(register);
+                    self.unnamed = Some('-');
+                }
+            },
+        }
+    }
+    // q{reg} puts the recorded keys in place without touching "" like a yank would
+    pub(crate) fn record(&mut self, name: char, keys: Vec<u8>) {
+        let register = Register::new(RegisterKind::Charwise, keys);
+        if !name.is_ascii_uppercase() {
+            self.write(name, register);
+            return;
+        }
+        match self.named.get_mut(&name.to_ascii_lowercase()) {
+            Some(existing) => existing.append(register),
+            None => {
+                self.named.insert(name.to_ascii_lowercase(), register);
+            }
+        }
+    }
+    fn write(&mut self, name: char, register: Register) {
+        match name {
+            '0'..='9' => self.numbered[name as usize - '0' as usize] = Some(register),
+            '-' => self.small_delete = Some(register),
+            _ => {
+                self.named.insert(name, register);
+            }
+        }
+    }
+    fn shift_deletes(&mut self, register: Register) {
+        for i in (2..10).rev() {
+            self.numbered[i] = self.numbered[i - 1].take();
+        }
+        self.numbered[1] = Some(register);
+    }
+    // `file` is the current file name, backing the "% register
+    pub(crate) fn get(&self, name: Option<char>, file: &str) -> Option<Register> {
+        let name = match name.unwrap_or('"') {
+            '"' => self.unnamed?,
+            n => n.to_ascii_lowercase(),
+        };
+        let charwise = |s: &str| {
+            if s.is_empty() {
+                None
+            } else {
+                Some(Register::new(RegisterKind::Charwise, s.as_bytes().to_vec()))
+            }
+        };
+        match name {
+            '0'..='9' => self.numbered[name as usize - '0' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            '%' => charwise(file),
+            ':' => charwise(&self.last_command),
+            '/' => charwise(&self.last_search),
+            '_' => None,
+            _ => self.named.get(&name).cloned(),
+        }
+    }
+    // Every register with content, in the order `:registers` prints them
+    pub(crate) fn list(&self, file: &str) -> Vec<(char, Register)> {
+        let mut names = vec!['"'];
+        names.extend('0'..='9');
+        names.extend('a'..='z');
+        names.extend(['-', '+', '*', '%', ':', '/']);
+        names
+            .into_iter()
+            .filter_map(|n| self.get(Some(n), file).map(|r| (n, r)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::{editor, type_script};
+
+    fn text(registers: &Registers, name: char) -> Option<Vec<u8>> {
+        registers.get(Some(name), "").map(|r| r.text)
+    }
+
+    #[test]
+    fn line_deletes_shift_through_the_numbered_registers() {
+        let mut registers = Registers::default();
+        registers.store(
+            RegisterOp::Delete,
+            RegisterKind::Linewise,
+            b"one\n".to_vec(),
+        );
+        registers.store(
+            RegisterOp::Delete,
+            RegisterKind::Linewise,
+            b"two\n".to_vec(),
+        );
+        assert_eq!(text(&registers, '1'), Some(b"two\n".to_vec()));
+        assert_eq!(text(&registers, '2'), Some(b"one\n".to_vec()));
+        assert_eq!(text(&registers, '"'), Some(b"two\n".to_vec()));
+    }
+
+    #[test]
+    fn small_deletes_go_to_minus_and_yanks_to_zero() {
+        let mut registers = Registers::default();
+        registers.store(RegisterOp::Yank, RegisterKind::Charwise, b"kept".to_vec());
+        registers.store(RegisterOp::Delete, RegisterKind::Charwise, b"x".to_vec());
+        assert_eq!(text(&registers, '-'), Some(b"x".to_vec()));
+        assert_eq!(text(&registers, '0'), Some(b"kept".to_vec()));
+        assert_eq!(text(&registers, '1'), None);
+        assert_eq!(text(&registers, '"'), Some(b"x".to_vec()));
+    }
+
+    #[test]
+    fn uppercase_appends_to_the_named_register() {
+        let mut registers = Registers::default();
+        registers.select('a');
+        registers.store(RegisterOp::Yank, RegisterKind::Charwise, b"foo".to_vec());
+        registers.select('A');
+        registers.store(RegisterOp::Yank, RegisterKind::Linewise, b"bar\n".to_vec());
+        let a = registers.get(Some('a'), "").unwrap();
+        assert_eq!(a.text, b"foo\nbar\n");
+        assert_eq!(a.kind, RegisterKind::Linewise);
+    }
+
+    #[test]
+    fn black_hole_and_read_only_registers_keep_nothing() {
+        let mut registers = Registers::default();
+        for name in ['_', '%', ':'] {
+            registers.select(name);
+            registers.store(
+                RegisterOp::Delete,
+                RegisterKind::Linewise,
+                b"gone\n".to_vec(),
+            );
+        }
+        assert_eq!(text(&registers, '"'), None);
+        assert_eq!(text(&registers, '1'), None);
+        assert_eq!(
+            registers.get(Some('%'), "file.txt").map(|r| r.text),
+            Some(b"file.txt".to_vec())
+        );
+        assert!(!registers.select('!'));
+    }
+
+    #[test]
+    fn named_delete_still_feeds_the_ring() {
+        let mut editor = editor(b"one\ntwo\n");
+        type_script(&mut editor, b"\"bdd");
+        let registers = &editor.context.borrow().registers;
+        assert_eq!(text(registers, 'b'), Some(b"one\n".to_vec()));
+        assert_eq!(text(registers, '1'), Some(b"one\n".to_vec()));
+    }
+}
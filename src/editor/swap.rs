@@ -0,0 +1,357 @@
+use chrono::offset::Utc;
+use chrono::DateTime;
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Swap files keep a copy of a buffer's text next to its file, as .name.swp, so the
+// changes outlive yim or its terminal dying. The copy is written again every
+// 'updatecount' keys typed into a modified buffer and after 'updatetime' milliseconds
+// without a key. It starts with a header saying who wrote it
+//
+//     yim swap file
+//     pid 1234
+//     file /home/me/notes.txt
+//
+// then an empty line and the text.
+
+const MAGIC: &str = "yim swap file";
+
+// The swap file of the buffer being edited
+#[derive(Debug)]
+pub struct SwapFile {
+    pub(crate) path: PathBuf,
+    // Keys handled since the text was last written into it
+    pub(crate) stale: usize,
+}
+
+// A swap file found for a file being opened, from a crash or another yim editing it
+#[derive(Debug)]
+pub struct FoundSwap {
+    pub(crate) path: PathBuf,
+    pub(crate) pid: i32,
+    pub(crate) file: String,
+    pub(crate) text: Vec<u8>,
+    pub(crate) modified: Option<SystemTime>,
+}
+
+// .name.swp, then .name.swo and on down to .name.swa when those are taken
+fn candidates(file: &Path) -> impl Iterator<Item = PathBuf> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = file.file_name().unwrap_or_default().as_bytes().to_vec();
+    (b'a'..=b'p').rev().map(move |c| {
+        let mut swap = vec![b'.'];
+        swap.extend_from_slice(&name);
+        swap.extend_from_slice(b".sw");
+        swap.push(c);
+        dir.join(OsString::from_vec(swap))
+    })
+}
+
+fn absolute(file: &Path) -> PathBuf {
+    match fs::canonicalize(file) {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir().unwrap_or_default().join(file),
+    }
+}
+
+// A swap file left for `file`, other than `own`
+pub(crate) fn find(file: &Path, own: Option<&Path>) -> Option<FoundSwap> {
+    candidates(file)
+        .filter(|path| Some(path.as_path()) != own)
+        .find_map(|path| read(&path).ok())
+}
+
+pub(crate) fn read(path: &Path) -> io::Result<FoundSwap> {
+    let bytes = fs::read(path)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a yim swap file");
+    let end = bytes
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .ok_or_else(invalid)?;
+    let header = String::from_utf8_lossy(&bytes[..end]).to_string();
+    let mut lines = header.lines();
+    if lines.next() != Some(MAGIC) {
+        return Err(invalid());
+    }
+    let mut found = FoundSwap {
+        path: path.to_path_buf(),
+        pid: 0,
+        file: String::new(),
+        text: bytes[end + 2..].to_vec(),
+        modified: fs::metadata(path).and_then(|m| m.modified()).ok(),
+    };
+    for line in lines {
+        match line.split_once(' ') {
+            Some(("pid", pid)) => found.pid = pid.parse().unwrap_or(0),
+            Some(("file", file)) => found.file = file.to_string(),
+            _ => (),
+        }
+    }
+    Ok(found)
+}
+
+// What yim -r shows: the swap files in `dir`
+pub fn list_swap_files(dir: &Path) -> Vec<String> {
+    let mut lines = vec![
+        String::from("Swap files found:"),
+        String::from("   In current directory:"),
+    ];
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().as_bytes();
+            name.starts_with(b".") && name.len() > 4 && name[..name.len() - 1].ends_with(b".sw")
+        })
+        .collect();
+    paths.sort();
+    let found: Vec<FoundSwap> = paths.iter().filter_map(|path| read(path).ok()).collect();
+    if found.is_empty() {
+        lines.push(String::from("      -- none --"));
+    }
+    for (i, swap) in found.iter().enumerate() {
+        let name = swap.path.file_name().unwrap_or_default().to_string_lossy();
+        lines.push(format!("{}.    {}", i + 1, name));
+        lines.extend(swap.describe());
+    }
+    lines
+}
+
+impl SwapFile {
+    // Takes the first free swap file name of `file` and writes `text` into it. The name
+    // is claimed by creating the file, two yims opening the same file can't both get it
+    pub(crate) fn create(file: &Path, text: &[u8]) -> io::Result<Self> {
+        for path in candidates(file) {
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)
+            {
+                Ok(_) => {
+                    let mut swap = Self { path, stale: 0 };
+                    if let Err(err) = swap.write(file, text) {
+                        swap.remove();
+                        return Err(err);
+                    }
+                    return Ok(swap);
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "E326: Too many swap files found",
+        ))
+    }
+    pub(crate) fn write(&mut self, file: &Path, text: &[u8]) -> io::Result<()> {
+        let header = format!(
+            "{}\npid {}\nfile {}\n\n",
+            MAGIC,
+            std::process::id(),
+            absolute(file).display()
+        );
+        let mut contents = header.into_bytes();
+        contents.extend_from_slice(text);
+        // Anyone who can't read the file can't read its swap file either, 0600 until
+        // the file exists
+        let mode = fs::metadata(file).map_or(0o600, |m| m.mode() & 0o666);
+        // The text goes to a file renamed over the swap file, a crash halfway through
+        // an update leaves the last one whole
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(".new");
+        let temp = PathBuf::from(name);
+        let _ = fs::remove_file(&temp);
+        let written = (|| {
+            let mut swap = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(mode)
+                .open(&temp)?;
+            swap.write_all(&contents)?;
+            swap.sync_all()?;
+            fs::rename(&temp, &self.path)
+        })();
+        if written.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        written?;
+        self.stale = 0;
+        Ok(())
+    }
+    pub(crate) fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl FoundSwap {
+    // The yim that wrote it is still there, editing the file right now
+    pub(crate) fn running(&self) -> bool {
+        if self.pid <= 0 {
+            return false;
+        }
+        let alive = unsafe { libc::kill(self.pid, 0) } == 0;
+        alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    fn describe(&self) -> Vec<String> {
+        let dated = match self.modified {
+            Some(time) => DateTime::<Utc>::from(time)
+                .format("%T %d/%m/%Y")
+                .to_string(),
+            None => String::from("?"),
+        };
+        vec![
+            format!("             dated: {}", dated),
+            format!("         file name: {}", self.file),
+            format!(
+                "        process ID: {}{}",
+                self.pid,
+                if self.running() {
+                    " (STILL RUNNING)"
+                } else {
+                    ""
+                }
+            ),
+        ]
+    }
+    // The E325 screen shown before editing `file`, it ends with the question
+    pub(crate) fn attention(&self, file: &str) -> Vec<String> {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let mut lines = vec![
+            String::from("E325: ATTENTION"),
+            format!("Found a swap file by the name \"{}\"", name),
+        ];
+        lines.extend(self.describe());
+        lines.push(format!("While opening file \"{}\"", file));
+        let newer = fs::metadata(file).and_then(|m| m.modified()).ok();
+        if newer.is_some() && self.modified.is_some() && newer > self.modified {
+            lines.push(String::from("      NEWER than swap file!"));
+        }
+        lines.extend([
+            String::new(),
+            String::from("(1) Another program may be editing the same file."),
+            String::from("(2) An edit session for this file crashed."),
+            format!(
+                "    (R)ecover or \"yim -r {}\" gets the changes back,",
+                file
+            ),
+            format!(
+                "    then delete the swap file \"{}\" to avoid this message.",
+                name
+            ),
+            String::new(),
+        ]);
+        lines.push(match self.running() {
+            true => String::from("[O]pen Read-Only, (E)dit anyway, (R)ecover, (Q)uit:"),
+            false => {
+                String::from("[O]pen Read-Only, (E)dit anyway, (R)ecover, (D)elete it, (Q)uit:")
+            }
+        });
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::testing::temp_dir;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn candidates_go_from_swp_down_to_swa() {
+        let names: Vec<PathBuf> = candidates(Path::new("dir/notes.txt")).collect();
+        assert_eq!(names.len(), 16);
+        assert_eq!(names[0], Path::new("dir/.notes.txt.swp"));
+        assert_eq!(names[1], Path::new("dir/.notes.txt.swo"));
+        assert_eq!(names[15], Path::new("dir/.notes.txt.swa"));
+        let here = candidates(Path::new("notes.txt")).next().unwrap();
+        assert_eq!(here, Path::new("./.notes.txt.swp"));
+    }
+
+    #[test]
+    fn candidates_keep_names_that_are_not_utf8() {
+        let file = Path::new(OsStr::from_bytes(b"caf\xe9.txt"));
+        let first = candidates(file).next().unwrap();
+        assert_eq!(first.file_name().unwrap().as_bytes(), b".caf\xe9.txt.swp");
+    }
+
+    #[test]
+    fn created_swap_file_reads_back() {
+        let dir = temp_dir("swap-round-trip");
+        let file = dir.join("file.txt");
+        let swap = SwapFile::create(&file, b"some\ntext\n").unwrap();
+        assert_eq!(swap.path, dir.join(".file.txt.swp"));
+        let found = read(&swap.path).unwrap();
+        assert_eq!(found.pid, std::process::id() as i32);
+        assert_eq!(found.text, b"some\ntext\n");
+        assert!(found.file.ends_with("file.txt"));
+        assert!(found.modified.is_some());
+        swap.remove();
+        assert!(!swap.path.exists());
+    }
+
+    #[test]
+    fn create_takes_the_next_name_when_one_is_taken() {
+        let dir = temp_dir("swap-taken");
+        let file = dir.join("file.txt");
+        fs::write(dir.join(".file.txt.swp"), b"someone else's").unwrap();
+        let swap = SwapFile::create(&file, b"text\n").unwrap();
+        assert_eq!(swap.path, dir.join(".file.txt.swo"));
+        assert_eq!(
+            fs::read(dir.join(".file.txt.swp")).unwrap(),
+            b"someone else's"
+        );
+        for path in candidates(&file).skip(2) {
+            fs::write(path, b"").unwrap();
+        }
+        let err = SwapFile::create(&file, b"text\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn find_skips_its_own_and_files_that_are_not_swap_files() {
+        let dir = temp_dir("swap-find");
+        let file = dir.join("file.txt");
+        fs::write(dir.join(".file.txt.swp"), b"not a swap file\n\nreally").unwrap();
+        assert!(find(&file, None).is_none());
+        let own = SwapFile::create(&file, b"mine\n").unwrap();
+        assert!(find(&file, Some(&own.path)).is_none());
+        let found = find(&file, None).unwrap();
+        assert_eq!(found.path, own.path);
+        assert_eq!(found.text, b"mine\n");
+    }
+
+    #[test]
+    fn attention_asks_about_deleting_only_when_the_writer_is_gone() {
+        let dir = temp_dir("swap-attention");
+        let file = dir.join("file.txt");
+        let own = SwapFile::create(&file, b"text\n").unwrap();
+        let mut found = read(&own.path).unwrap();
+        assert!(found.running());
+        let name = file.to_str().unwrap();
+        let lines = found.attention(name);
+        assert_eq!(lines[0], "E325: ATTENTION");
+        assert_eq!(lines[1], "Found a swap file by the name \".file.txt.swp\"");
+        assert!(lines.iter().any(|line| line.ends_with("(STILL RUNNING)")));
+        assert_eq!(
+            lines.last().unwrap(),
+            "[O]pen Read-Only, (E)dit anyway, (R)ecover, (Q)uit:"
+        );
+        found.pid = 0;
+        assert!(!found.running());
+        let lines = found.attention(name);
+        assert!(!lines.iter().any(|line| line.contains("STILL RUNNING")));
+        assert!(lines.last().unwrap().contains("(D)elete it"));
+    }
+}
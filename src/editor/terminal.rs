@@ -1,9 +1,10 @@
 use std::cell::RefCell;
 use std::fmt;
-use std::io::{Read, Stdin, Stdout, Write};
+use std::io::{Stdin, Stdout, Write};
 use std::os::fd::AsRawFd;
 use std::process::exit;
 use std::rc::Rc;
+use std::time::Duration;
 
 extern crate libc;
 
@@ -123,16 +124,35 @@ impl Terminal {
         let mut buf = [0u8; 1];
         let mut error_handle = false;
         self.stdout.lock().flush().unwrap();
-        self.stdin.read_exact(&mut buf).map_err(|err| {
+        if let Err(err) = self.read_raw(&mut buf) {
             log::error!("cannot read key {err}");
             error_handle = true;
-        });
+        }
         if !error_handle {
             res = Some(buf[0]);
         }
         res
     }
 
+    // None when no key comes within `timeout`
+    pub(crate) fn read_key_within(&mut self, timeout: Duration) -> Option<u8> {
+        match self.key_within(timeout) {
+            true => self.read_key(),
+            false => None,
+        }
+    }
+    // A key comes within `timeout`, it is left to read
+    pub(crate) fn key_within(&mut self, timeout: Duration) -> bool {
+        self.stdout.lock().flush().unwrap();
+        let mut fds = libc::pollfd {
+            fd: self.stdin.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) };
+        ready > 0
+    }
+
     pub(crate) fn flush(&mut self) {
         self.stdout.lock().flush().unwrap();
     }
@@ -145,9 +165,24 @@ impl Terminal {
     }
     pub(crate) fn read(&mut self, buff: &mut [u8]) {
         //let mut buffer = [0u8; 32];
-        self.stdin.read(buff).unwrap();
+        self.read_raw(buff).unwrap();
         //buffer
     }
+    // Straight from the descriptor, Stdin would buffer keys poll can't see anymore
+    fn read_raw(&mut self, buff: &mut [u8]) -> std::io::Result<usize> {
+        let read = unsafe {
+            libc::read(
+                self.stdin.as_raw_fd(),
+                buff.as_mut_ptr() as *mut libc::c_void,
+                buff.len(),
+            )
+        };
+        match read {
+            0 => Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n if n < 0 => Err(std::io::Error::last_os_error()),
+            n => Ok(n as usize),
+        }
+    }
 }
 impl fmt::Debug for Terminal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
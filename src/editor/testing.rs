@@ -0,0 +1,46 @@
+use std::fs;
+use std::io::{stdin, stdout};
+use std::path::{Path, PathBuf};
+
+use crate::editor::{Editor, EditorHealth, KeySource};
+
+// Sized like an 80x24 terminal but never drawn
+fn sized_editor() -> Editor {
+    let editor = Editor::new(stdin(), stdout());
+    editor.cursor.borrow_mut().rows = 22;
+    editor.cursor.borrow_mut().cols = 80;
+    editor
+}
+
+// An editor with `text` in its buffer
+pub(crate) fn editor(text: &[u8]) -> Editor {
+    let mut editor = sized_editor();
+    editor.open_stdin(text.to_vec());
+    editor
+}
+
+// An editor on `path` like `yim -n path` starts one
+pub(crate) fn editor_on(path: &Path) -> Editor {
+    let mut editor = sized_editor();
+    editor.execute_command("set noswapfile");
+    editor.open(path.to_str().unwrap()).unwrap();
+    editor
+}
+
+// Handles `keys` from the queue until it is empty, the terminal is never read
+pub(crate) fn type_script(editor: &mut Editor, keys: &[u8]) {
+    let mut c = editor.context.borrow_mut();
+    c.input.push_back(keys, KeySource::Script);
+    drop(c);
+    while !editor.context.borrow().input.is_empty() {
+        assert!(editor.process_key_press() == Some(EditorHealth::Healthy));
+    }
+}
+
+// A fresh directory for one test to put files in, left behind in the temp dir
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("yim-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
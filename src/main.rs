@@ -1,6 +1,8 @@
 use log;
 use std::env;
-use std::io::{stdin, stdout};
+use std::io::{stdin, stdout, Read, Write};
+use std::os::fd::AsRawFd;
+use std::process::exit;
 use std::string::String;
 use syslog::Facility;
 
@@ -13,30 +15,238 @@ mod editor;
 //pub use crate::editor::engine::OpStack;
 //pub use crate::editor::engine::Operator;
 pub use crate::editor::Editor;
-pub use crate::editor::EditorConfigs;
+pub use crate::editor::EditorHealth;
+
+const USAGE: &str = "usage: yim [arguments] [file ..]       edit specified file(s)
+   or: yim [arguments] -               read text from stdin
+
+Arguments:
+   --                   Only file names after this
+   +                    Start at end of file
+   +{lnum}              Start at line {lnum}
+   +/{pat}              Start at first occurrence of {pat}
+   +{command}           Execute {command} after loading the first file
+   -c {command}         Execute {command} after loading the first file
+   -s {scriptin}        Type the keys in {scriptin} before the keyboard is read
+   -R                   Read-only mode
+   -n                   No swap file
+   -r                   List swap files and exit
+   -r {file}            Recover the changes to {file} from its swap file
+   -u {yimrc}           Use {yimrc} instead of yimrc, NONE skips it
+   --clean              No yimrc and no swap file
+   -h, --help           Print this help and exit
+   --version            Print version information and exit";
+
+enum Yimrc {
+    Default,
+    Skip,
+    File(String),
+}
+
+// What `yim -h` lists
+struct Arguments {
+    files: Vec<String>,
+    stdin: bool,
+    // Ex commands run once the first file is loaded, from -c and +
+    commands: Vec<String>,
+    scripts: Vec<String>,
+    readonly: bool,
+    swap: bool,
+    recover: bool,
+    yimrc: Yimrc,
+}
+
+fn parse_arguments(args: &[String]) -> Result<Arguments, String> {
+    let mut parsed = Arguments {
+        files: Vec::new(),
+        stdin: false,
+        commands: Vec::new(),
+        scripts: Vec::new(),
+        readonly: false,
+        swap: true,
+        recover: false,
+        yimrc: Yimrc::Default,
+    };
+    let mut args = args.iter();
+    let mut only_files = false;
+    while let Some(arg) = args.next() {
+        if only_files {
+            parsed.files.push(arg.clone());
+            continue;
+        }
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or(format!("Argument missing after: \"{}\"", name))
+        };
+        match arg.as_str() {
+            "--" => only_files = true,
+            "-" => parsed.stdin = true,
+            "-c" => parsed.commands.push(value(arg)?),
+            "-s" => parsed.scripts.push(value(arg)?),
+            "-u" => {
+                parsed.yimrc = match value(arg)?.as_str() {
+                    "NONE" | "NORC" => Yimrc::Skip,
+                    file => Yimrc::File(file.to_string()),
+                }
+            }
+            "-R" => parsed.readonly = true,
+            "-n" => parsed.swap = false,
+            "-r" => parsed.recover = true,
+            "--clean" => {
+                parsed.yimrc = Yimrc::Skip;
+                parsed.swap = false;
+            }
+            "-h" | "--help" => {
+                let _ = writeln!(stdout(), "{}", USAGE);
+                exit(0);
+            }
+            "--version" => {
+                let _ = writeln!(stdout(), "yim {}", env!("CARGO_PKG_VERSION"));
+                exit(0);
+            }
+            // + alone is the last line
+            command if command.starts_with('+') => match &command[1..] {
+                "" => parsed.commands.push(String::from("$")),
+                command => parsed.commands.push(command.to_string()),
+            },
+            option if option.starts_with('-') => {
+                return Err(format!("Unknown option argument: \"{}\"", option))
+            }
+            file => parsed.files.push(file.to_string()),
+        }
+    }
+    if parsed.stdin && !parsed.files.is_empty() {
+        return Err(String::from("Too many edit arguments: \"-\""));
+    }
+    Ok(parsed)
+}
 
 fn main() -> std::io::Result<()> {
     //engine_example();
-    let ed = EditorConfigs {
-        x_offset: 4,
-        y_offset: 0,
-    };
     syslog::init(Facility::LOG_USER, log::LevelFilter::Debug, Some("yim")).unwrap();
     log::info!("Launching yim...");
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arguments = match parse_arguments(&args) {
+        Ok(arguments) => arguments,
+        Err(err) => {
+            eprintln!("yim: {}\nMore info with: \"yim -h\"", err);
+            exit(1);
+        }
+    };
+    if arguments.recover && arguments.files.is_empty() {
+        for line in editor::list_swap_files(std::path::Path::new(".")) {
+            let _ = writeln!(stdout(), "{}", line);
+        }
+        exit(0);
+    }
+    // The text comes through stdin, the keys from the terminal behind it
+    let mut text = Vec::new();
+    if arguments.stdin {
+        stdin().read_to_end(&mut text)?;
+        let tty = std::fs::File::open("/dev/tty")?;
+        unsafe { libc::dup2(tty.as_raw_fd(), libc::STDIN_FILENO) };
+    }
 
     let stdin = stdin();
     let stdout = stdout();
     let mut editor = Editor::new(stdin, stdout);
-    editor.editor_configs = ed;
 
     editor.init_editor();
-    let mut openfile = "";
-    if args.len() > 1 {
-        openfile = &args[1];
-        editor.open(openfile)?;
-    }
     editor.set_status_message("Welcome Yuksel!");
+    match &arguments.yimrc {
+        Yimrc::Default => editor.source_yimrc(),
+        Yimrc::Skip => (),
+        Yimrc::File(path) => {
+            if editor.source(path).is_err() {
+                editor.set_status_message(&format!("E282: Cannot read from \"{}\"", path));
+            }
+        }
+    }
+    if arguments.readonly {
+        editor.execute_command("set readonly");
+    }
+    if !arguments.swap {
+        editor.execute_command("set noswapfile");
+    }
+    // The scripts are typed as if they came from the keyboard
+    for script in arguments.scripts.iter() {
+        editor.feed_script(&std::fs::read(script)?);
+    }
+    if arguments.stdin {
+        editor.open_stdin(text);
+    } else if let Some(file) = arguments.files.first() {
+        editor.open(file)?;
+        if arguments.recover {
+            editor.recover();
+        }
+    }
+    editor.context.borrow_mut().arglist = arguments.files;
+    for command in arguments.commands.iter() {
+        let command = command.trim_start_matches(':');
+        let health = match command.starts_with('/') {
+            true => editor.search_from_top(command),
+            false => editor.execute_command(command),
+        };
+        if health == Some(EditorHealth::Exit) {
+            return Ok(());
+        }
+    }
     editor.launch_engine();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Arguments, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_arguments(&args)
+    }
+
+    #[test]
+    fn plus_and_c_commands_keep_their_order() {
+        let parsed = parse(&["+", "file", "+42", "-c", "set list", "+/pat"]).unwrap();
+        assert_eq!(parsed.commands, ["$", "42", "set list", "/pat"]);
+        assert_eq!(parsed.files, ["file"]);
+    }
+
+    #[test]
+    fn flags_and_clean() {
+        let parsed = parse(&["-R", "-n", "file"]).unwrap();
+        assert!(parsed.readonly);
+        assert!(!parsed.swap);
+        assert!(matches!(parsed.yimrc, Yimrc::Default));
+        let parsed = parse(&["--clean"]).unwrap();
+        assert!(!parsed.readonly);
+        assert!(!parsed.swap);
+        assert!(matches!(parsed.yimrc, Yimrc::Skip));
+    }
+
+    #[test]
+    fn u_takes_none_or_a_file() {
+        assert!(matches!(parse(&["-u", "NONE"]).unwrap().yimrc, Yimrc::Skip));
+        match parse(&["-u", "my.yimrc"]).unwrap().yimrc {
+            Yimrc::File(file) => assert_eq!(file, "my.yimrc"),
+            _ => panic!("-u with a file name"),
+        }
+    }
+
+    #[test]
+    fn only_files_after_double_dash() {
+        let parsed = parse(&["-n", "--", "-x", "+1"]).unwrap();
+        assert_eq!(parsed.files, ["-x", "+1"]);
+        assert!(parsed.commands.is_empty());
+    }
+
+    #[test]
+    fn bad_arguments_are_refused() {
+        let err = |args: &[&str]| parse(args).err().unwrap();
+        assert_eq!(err(&["-", "file"]), "Too many edit arguments: \"-\"");
+        assert_eq!(err(&["-c"]), "Argument missing after: \"-c\"");
+        assert_eq!(err(&["file", "-u"]), "Argument missing after: \"-u\"");
+        assert_eq!(err(&["-z"]), "Unknown option argument: \"-z\"");
+        assert!(parse(&["-"]).unwrap().stdin);
+    }
+}